@@ -0,0 +1,41 @@
+//! Compares the propagation strategies by timing full generations of each sample.
+//!
+//! Run with `cargo bench --bench propagation`.
+
+use std::time::{Duration, Instant};
+
+use wfc_overlapping_model::{grid::Propagation, GeneratorBuilder};
+
+const SAMPLES: [&str; 3] = ["city.png", "Flowers.png", "waves.png"];
+const SEEDS: u64 = 5;
+const SIZE: usize = 64;
+
+fn main() {
+    for sample in SAMPLES {
+        let image = image::open(format!("tile_examples/{sample}"))
+            .expect("Example tiles should exist")
+            .into_rgb8();
+        for (name, propagation) in [
+            ("union", Propagation::Full),
+            ("support count", Propagation::SupportCount),
+        ] {
+            let mut total = Duration::ZERO;
+            for seed in 0..SEEDS {
+                let start = Instant::now();
+                GeneratorBuilder::new(image.clone())
+                    .output_size(SIZE, SIZE)
+                    .propagation(propagation)
+                    .backtracking(1000)
+                    .seed(seed)
+                    .build()
+                    .and_then(|mut generator| generator.generate())
+                    .expect("Example tiles should generate");
+                total += start.elapsed();
+            }
+            println!(
+                "{sample:<12} {name:<14} {:>10.2?} per output",
+                total / SEEDS as u32
+            );
+        }
+    }
+}
@@ -2,15 +2,48 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
-use crate::image_impls::BorderStyle;
+use wfc_overlapping_model::{
+    constraint::EdgeConstraint,
+    grid::{Propagation, Wrapping},
+    heuristic::Heuristic,
+    image_impls::{BorderStyle, Symmetry},
+    render::Render,
+    value_selector::ValueSelection,
+};
 
 #[derive(Parser)]
 #[command(version, about)]
 pub struct Args {
-    #[arg(short, long)]
-    pub input: PathBuf,
+    #[arg(short, long, required_unless_present = "ruleset")]
+    pub input: Option<PathBuf>,
+    /// Ruleset saved with `--save-ruleset` to generate from instead of an input image,
+    /// the tile options of the saved ruleset are used
+    #[arg(long, conflicts_with = "input")]
+    pub ruleset: Option<PathBuf>,
+    /// Saves the patterns extracted from the input so that later runs can skip extraction
+    #[arg(long)]
+    pub save_ruleset: Option<PathBuf>,
+    /// Output image, with `--count` a template where `{index}` and `{seed}` are replaced for each output,
+    /// `out_{index}_{seed}.png` by default
     #[arg(short, long)]
     pub output: Option<PathBuf>,
+    /// Generate this many outputs, extracting the tiles only once
+    #[arg(long, conflicts_with_all = ["display", "repeating"])]
+    pub count: Option<usize>,
+    /// Generate the outputs of `--count` in parallel
+    #[arg(long, requires = "count")]
+    pub parallel: bool,
+    /// Image the size of the output whose opaque pixels fix the color of the output at that cell
+    #[arg(long)]
+    pub constraint_image: Option<PathBuf>,
+    /// Image the size of the output whose bright, opaque pixels mark the cells that are generated,
+    /// other cells are left transparent
+    #[arg(long)]
+    pub mask: Option<PathBuf>,
+    /// Limits an edge of the output to tiles from the same edge of the input, as `<edge>`,
+    /// or to a color, as `<edge>=#rrggbb`. Edges are top, bottom, left, right or all
+    #[arg(long = "edge")]
+    pub edge_constraints: Vec<EdgeConstraint>,
     #[arg(short, long, default_value_t = 3)]
     pub tile_size: u32,
     #[arg(long = "height")]
@@ -19,10 +52,72 @@ pub struct Args {
     pub output_width: usize,
     #[arg(long)]
     pub display: bool,
+    /// How the output image is drawn from the collapsed cells
+    #[arg(long, value_enum, default_value_t = Render::Center)]
+    pub render: Render,
+    /// Integer factor the output image is upscaled by, at least 1
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub scale: u32,
+    /// Initial propagation depth when using depth limited propagation
     #[arg(long, default_value_t = 10)]
     pub max_depth: usize,
+    #[arg(long, value_enum, default_value_t = Propagation::Full)]
+    pub propagation: Propagation,
+    /// Wrap the output around its edges so that it tiles seamlessly
+    #[arg(long, value_enum, default_value_t = Wrapping::None)]
+    pub wrap: Wrapping,
+    #[arg(long, value_enum, default_value_t = Heuristic::MinEntropy)]
+    pub heuristic: Heuristic,
+    #[arg(long, value_enum, default_value_t = ValueSelection::Weighted)]
+    pub value_selector: ValueSelection,
+    /// Temperature for the temperature value selector, must be positive
+    #[arg(long, default_value_t = 1.0, value_parser = parse_temperature)]
+    pub temperature: f64,
+    /// Give up after this many attempts end in a contradiction instead of restarting until one succeeds, at least 1
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_attempts: Option<usize>,
+    /// Run this many attempts in parallel, each seeded from the seed and giving up on its first contradiction,
+    /// and keep the first one that succeeds
+    #[arg(long, default_value_t = 1)]
+    pub attempts: usize,
+    /// Number of threads for parallel attempts and parallel outputs, one per core if omitted. Parallel outputs share
+    /// the threads, splitting what is left over between their attempts
+    #[arg(long)]
+    pub threads: Option<usize>,
+    /// Undo the last decisions on a contradiction instead of restarting
+    #[arg(long)]
+    pub backtrack: bool,
+    /// Number of backtracks allowed before restarting
+    #[arg(long, default_value_t = 1000)]
+    pub max_backtracks: usize,
     #[arg(long, value_enum, default_value_t = BorderStyle::Looping)]
     pub border_style: BorderStyle,
+    #[arg(long, value_enum, default_value_t = Symmetry::None)]
+    pub symmetry: Symmetry,
+    /// Reduce the input to at most this many colors with k-means before extracting tiles, at least 1
+    #[arg(
+        long,
+        conflicts_with = "tolerance",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub colors: Option<usize>,
+    /// Merge input colors whose channels all differ by at most this much, from 0 to 255
+    #[arg(long)]
+    pub tolerance: Option<f64>,
+    /// Fully transparent pixels in the input match any color when comparing tiles
+    #[arg(long)]
+    pub wildcards: bool,
     #[arg(long = "repeat")]
     pub repeating: bool,
+    /// Seed for the generator, a random seed is chosen and printed if omitted
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+fn parse_temperature(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(temperature) if temperature > 0. && temperature.is_finite() => Ok(temperature),
+        Ok(_) => Err("temperature must be a positive number".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
 }
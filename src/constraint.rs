@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use image::Rgb;
+
+use crate::tile::Direction;
+
+/// Limits the cells along an edge of the output
+#[derive(Clone, Copy, Debug)]
+pub struct EdgeConstraint {
+    pub edge: Edge,
+    pub source: EdgeSource,
+}
+
+/// An edge of the output, or every edge at once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+    All,
+}
+
+/// Which tiles cells along a constrained edge may use
+#[derive(Clone, Copy, Debug)]
+pub enum EdgeSource {
+    /// Tiles that were centered on the same edge of the sample
+    Sample,
+    /// Tiles whose center pixel is this color
+    Color(Rgb<u8>),
+}
+
+impl Edge {
+    pub fn directions(&self) -> &'static [Direction] {
+        match self {
+            Edge::Top => &[Direction::Up],
+            Edge::Bottom => &[Direction::Down],
+            Edge::Left => &[Direction::Left],
+            Edge::Right => &[Direction::Right],
+            Edge::All => &Direction::ALL,
+        }
+    }
+}
+
+impl FromStr for Edge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top" => Ok(Edge::Top),
+            "bottom" => Ok(Edge::Bottom),
+            "left" => Ok(Edge::Left),
+            "right" => Ok(Edge::Right),
+            "all" => Ok(Edge::All),
+            _ => Err(format!(
+                "unknown edge `{s}`, expected top, bottom, left, right or all"
+            )),
+        }
+    }
+}
+
+/// Parses `<edge>` to use tiles from the same edge of the sample, or `<edge>=<#rrggbb>` to use a color
+impl FromStr for EdgeConstraint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (edge, source) = match s.split_once('=') {
+            Some((edge, color)) => (edge, EdgeSource::Color(parse_color(color)?)),
+            None => (s, EdgeSource::Sample),
+        };
+        Ok(EdgeConstraint {
+            edge: edge.parse()?,
+            source,
+        })
+    }
+}
+
+fn parse_color(s: &str) -> Result<Rgb<u8>, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or_else(|| format!("invalid color `{s}`, expected #rrggbb"))?;
+    let [_, r, g, b] = value.to_be_bytes();
+    Ok(Rgb([r, g, b]))
+}
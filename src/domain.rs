@@ -0,0 +1,138 @@
+use std::iter;
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A set of tile indexes below a fixed size, stored as a bitset
+#[derive(Clone, Default, Debug, PartialEq, Eq, Hash)]
+pub struct Domain {
+    words: Vec<u64>,
+    size: usize,
+}
+
+impl Domain {
+    /// A domain that can hold indexes below `size` with none of them set
+    pub fn empty(size: usize) -> Domain {
+        Domain {
+            words: vec![0; size.div_ceil(WORD_BITS)],
+            size,
+        }
+    }
+
+    /// A domain containing every index below `size`
+    pub fn full(size: usize) -> Domain {
+        let mut domain = Domain::empty(size);
+        domain.words.fill(u64::MAX);
+        domain.clear_excess();
+        domain
+    }
+
+    pub fn from_indexes(size: usize, indexes: impl IntoIterator<Item = usize>) -> Domain {
+        let mut domain = Domain::empty(size);
+        for index in indexes {
+            domain.insert(index);
+        }
+        domain
+    }
+
+    /// The number of indexes this domain can hold
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        index < self.size && self.words[index / WORD_BITS] & (1 << (index % WORD_BITS)) != 0
+    }
+
+    /// Adds an index, returning whether it was newly added
+    pub fn insert(&mut self, index: usize) -> bool {
+        assert!(index < self.size, "Index out of range of domain");
+        let word = &mut self.words[index / WORD_BITS];
+        let bit = 1 << (index % WORD_BITS);
+        let added = *word & bit == 0;
+        *word |= bit;
+        added
+    }
+
+    /// Removes an index, returning whether it was present
+    pub fn remove(&mut self, index: usize) -> bool {
+        if index >= self.size {
+            return false;
+        }
+        let word = &mut self.words[index / WORD_BITS];
+        let bit = 1 << (index % WORD_BITS);
+        let removed = *word & bit != 0;
+        *word &= !bit;
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+
+    pub fn union_with(&mut self, other: &Domain) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    pub fn intersect_with(&mut self, other: &Domain) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= other;
+        }
+    }
+
+    /// The number of indexes in both domains, without building the intersection
+    pub fn intersection_len(&self, other: &Domain) -> usize {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .map(|(word, other)| (word & other).count_ones() as usize)
+            .sum()
+    }
+
+    /// Iterates over the indexes in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &word)| word_indexes(i, word))
+    }
+
+    /// Iterates over the indexes in this domain but not in `other`, in ascending order
+    pub fn difference<'a>(&'a self, other: &'a Domain) -> impl Iterator<Item = usize> + 'a {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .enumerate()
+            .flat_map(|(i, (word, other))| word_indexes(i, word & !other))
+    }
+
+    fn clear_excess(&mut self) {
+        let excess = self.words.len() * WORD_BITS - self.size;
+        if let Some(last) = self.words.last_mut().filter(|_| excess != 0) {
+            *last &= u64::MAX >> excess;
+        }
+    }
+}
+
+fn word_indexes(word_index: usize, mut word: u64) -> impl Iterator<Item = usize> {
+    iter::from_fn(move || {
+        if word == 0 {
+            return None;
+        }
+        let bit = word.trailing_zeros() as usize;
+        word &= word - 1;
+        Some(word_index * WORD_BITS + bit)
+    })
+}
@@ -0,0 +1,73 @@
+use std::{error, fmt, path::PathBuf};
+
+/// Everything that can stop a generation
+#[derive(Debug)]
+pub enum Error {
+    /// Tiles need an odd size so that they have a center pixel, and must fit inside the sample
+    InvalidTileSize(u32),
+    /// The output needs at least one cell in each direction
+    InvalidOutputSize(usize, usize),
+    /// An input image, constraint image, mask or ruleset could not be read
+    UnreadableInput {
+        path: PathBuf,
+        source: Box<dyn error::Error + Send + Sync>,
+    },
+    /// An output image or ruleset could not be written
+    UnwritableOutput {
+        path: PathBuf,
+        source: Box<dyn error::Error + Send + Sync>,
+    },
+    /// The ruleset has no tile with a non-zero frequency, so no cell has any options
+    NoTiles,
+    /// A cell ran out of options
+    Contradiction,
+    /// Every allowed attempt ended in a contradiction
+    AttemptLimit(usize),
+    /// A constraint that no tile can satisfy, or that does not fit the output
+    InvalidConstraint(String),
+    /// The value selector chose no option, or an option the cell does not have, for the cell at this grid index
+    InvalidChoice(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidTileSize(tile_size) => {
+                write!(
+                    f,
+                    "tile size must be odd and fit inside the sample, got {tile_size}"
+                )
+            }
+            Error::InvalidOutputSize(width, height) => {
+                write!(f, "output size must not be zero, got {width}x{height}")
+            }
+            Error::UnreadableInput { path, source } => {
+                write!(f, "could not read {}: {source}", path.display())
+            }
+            Error::UnwritableOutput { path, source } => {
+                write!(f, "could not write {}: {source}", path.display())
+            }
+            Error::NoTiles => write!(f, "the ruleset has no tiles to place"),
+            Error::Contradiction => write!(f, "a cell ran out of options"),
+            Error::AttemptLimit(attempts) => {
+                write!(f, "gave up after {attempts} failed attempts")
+            }
+            Error::InvalidConstraint(reason) => write!(f, "invalid constraint: {reason}"),
+            Error::InvalidChoice(grid_index) => write!(
+                f,
+                "the value selector chose no valid option for cell {grid_index}"
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::UnreadableInput { source, .. } | Error::UnwritableOutput { source, .. } => {
+                Some(source.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
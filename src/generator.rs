@@ -0,0 +1,551 @@
+use std::sync::Arc;
+
+use image::{GrayAlphaImage, Pixel, Rgb, Rgba, RgbaImage};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    constraint::{EdgeConstraint, EdgeSource},
+    domain::Domain,
+    error::Error,
+    grid::{Grid, Propagation, Wrapping},
+    heuristic::{MinEntropy, SelectionHeuristic},
+    image_impls::{BorderStyle, Symmetry, TilePixel},
+    quantize::{self, Quantization, QuantizeReport},
+    render::{self, Render},
+    ruleset::{self, Ruleset},
+    tile::{self, Direction, Tile, TileImage},
+    value_selector::{ValueSelector, Weighted},
+};
+
+/// Configures a [`Generator`] from a sample image or a saved [`Ruleset`].
+pub struct GeneratorBuilder<P: TilePixel = Rgba<u8>, R = ChaCha8Rng> {
+    source: Source<P>,
+    tile_size: u32,
+    border_style: BorderStyle,
+    symmetry: Symmetry,
+    wildcards: bool,
+    quantization: Option<Quantization>,
+    width: usize,
+    height: usize,
+    max_depth: usize,
+    propagation: Propagation,
+    wrapping: Wrapping,
+    max_backtracks: Option<usize>,
+    max_attempts: Option<usize>,
+    heuristic: Box<dyn SelectionHeuristic + Send>,
+    selector: Box<dyn ValueSelector<P> + Send>,
+    constraint_image: Option<RgbaImage>,
+    edge_constraints: Vec<EdgeConstraint>,
+    mask: Option<Vec<bool>>,
+    mask_image: Option<GrayAlphaImage>,
+    rng: R,
+    seed: Option<u64>,
+}
+
+/// Where the tiles of a [`GeneratorBuilder`] come from
+enum Source<P: TilePixel> {
+    Sample(TileImage<P>),
+    /// The sample options, such as the tile size and symmetry, are ignored
+    Ruleset(Arc<Ruleset<P>>),
+}
+
+/// A step-wise handle on the collapse of a single output grid.
+pub struct Generator<P: TilePixel = Rgba<u8>, R = ChaCha8Rng> {
+    ruleset: Arc<Ruleset<P>>,
+    grid: Grid,
+    heuristic: Box<dyn SelectionHeuristic + Send>,
+    selector: Box<dyn ValueSelector<P> + Send>,
+    max_attempts: Option<usize>,
+    rng: R,
+    seed: Option<u64>,
+    quantize_report: Option<QuantizeReport>,
+}
+
+impl<P: TilePixel> GeneratorBuilder<P> {
+    /// Starts from a randomly chosen seed, see [`GeneratorBuilder::seed`]
+    pub fn new(sample: TileImage<P>) -> GeneratorBuilder<P> {
+        GeneratorBuilder::from_source(Source::Sample(sample))
+    }
+
+    /// Uses the patterns of an already built ruleset instead of extracting them from a sample
+    pub fn from_ruleset(ruleset: impl Into<Arc<Ruleset<P>>>) -> GeneratorBuilder<P> {
+        GeneratorBuilder::from_source(Source::Ruleset(ruleset.into()))
+    }
+
+    fn from_source(source: Source<P>) -> GeneratorBuilder<P> {
+        let seed = rand::random();
+        GeneratorBuilder {
+            source,
+            tile_size: 3,
+            border_style: BorderStyle::default(),
+            symmetry: Symmetry::default(),
+            wildcards: false,
+            quantization: None,
+            width: 20,
+            height: 20,
+            max_depth: 10,
+            propagation: Propagation::default(),
+            wrapping: Wrapping::default(),
+            max_backtracks: None,
+            max_attempts: None,
+            heuristic: Box::new(MinEntropy::default()),
+            selector: Box::new(Weighted),
+            constraint_image: None,
+            edge_constraints: Vec::new(),
+            mask: None,
+            mask_image: None,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed: Some(seed),
+        }
+    }
+
+    /// Seeds the generator so that the same seed and settings always produce the same output
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl<P: TilePixel, R: Rng> GeneratorBuilder<P, R> {
+    /// Width and height of the extracted patterns, must be odd
+    pub fn tile_size(mut self, tile_size: u32) -> Self {
+        self.tile_size = tile_size;
+        self
+    }
+
+    pub fn border_style(mut self, border_style: BorderStyle) -> Self {
+        self.border_style = border_style;
+        self
+    }
+
+    /// Adds rotated and reflected copies of each extracted tile
+    pub fn symmetry(mut self, symmetry: Symmetry) -> Self {
+        self.symmetry = symmetry;
+        self
+    }
+
+    /// Lets fully transparent sample pixels match any pixel when merging and matching tiles,
+    /// for samples with "don't care" regions
+    pub fn transparent_wildcards(mut self, wildcards: bool) -> Self {
+        self.wildcards = wildcards;
+        self
+    }
+
+    /// Reduces the sample's colors before extracting tiles, so that noisy samples give fewer patterns
+    pub fn quantize(mut self, quantization: Quantization) -> Self {
+        self.quantization = Some(quantization);
+        self
+    }
+
+    /// Size of the output in cells
+    pub fn output_size(mut self, width: usize, height: usize) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Initial depth for [`Propagation::DepthLimited`], doubled on every failed attempt
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn propagation(mut self, propagation: Propagation) -> Self {
+        self.propagation = propagation;
+        self
+    }
+
+    /// Wraps the output around its edges so that it tiles seamlessly
+    pub fn wrapping(mut self, wrapping: Wrapping) -> Self {
+        self.wrapping = wrapping;
+        self
+    }
+
+    /// Backtrack on contradictions instead of restarting, restarting only once `max_backtracks` is used up
+    pub fn backtracking(mut self, max_backtracks: usize) -> Self {
+        self.max_backtracks = Some(max_backtracks);
+        self
+    }
+
+    /// Gives up with [`Error::AttemptLimit`] after this many attempts end in a contradiction, unlimited by default.
+    /// The first attempt always runs, so 0 gives up after one attempt like 1
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// How the next cell to collapse is chosen, defaults to [`MinEntropy`]
+    pub fn heuristic(mut self, heuristic: impl SelectionHeuristic + Send + 'static) -> Self {
+        self.heuristic = Box::new(heuristic);
+        self
+    }
+
+    /// How a collapsing cell's tile is chosen, defaults to [`Weighted`]
+    pub fn value_selector(mut self, selector: impl ValueSelector<P> + Send + 'static) -> Self {
+        self.selector = Box::new(selector);
+        self
+    }
+
+    /// Fixes the color of every cell where the image is opaque, leaving transparent cells free.
+    /// The image must be the size of the output
+    pub fn constraint_image(mut self, image: RgbaImage) -> Self {
+        self.constraint_image = Some(image);
+        self
+    }
+
+    /// Limits the cells along an edge of the output, can be called multiple times
+    pub fn edge_constraint(mut self, constraint: EdgeConstraint) -> Self {
+        self.edge_constraints.push(constraint);
+        self
+    }
+
+    /// Limits the output to the cells where the mask, one entry per cell in reading order, is `true`
+    pub fn mask(mut self, mask: Vec<bool>) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// Limits the output to the cells where the image is bright and opaque, the image must be the size of the output
+    pub fn mask_image(mut self, image: GrayAlphaImage) -> Self {
+        self.mask_image = Some(image);
+        self
+    }
+
+    /// Replaces the seeded rng, the resulting generator will not report a seed
+    pub fn rng<T: Rng>(self, rng: T) -> GeneratorBuilder<P, T> {
+        GeneratorBuilder {
+            source: self.source,
+            tile_size: self.tile_size,
+            border_style: self.border_style,
+            symmetry: self.symmetry,
+            wildcards: self.wildcards,
+            quantization: self.quantization,
+            width: self.width,
+            height: self.height,
+            max_depth: self.max_depth,
+            propagation: self.propagation,
+            wrapping: self.wrapping,
+            max_backtracks: self.max_backtracks,
+            max_attempts: self.max_attempts,
+            heuristic: self.heuristic,
+            selector: self.selector,
+            constraint_image: self.constraint_image,
+            edge_constraints: self.edge_constraints,
+            mask: self.mask,
+            mask_image: self.mask_image,
+            rng,
+            seed: None,
+        }
+    }
+
+    /// Extracts the tiles and applies the constraints, failing if the tile size is even or a constraint cannot be met
+    pub fn build(self) -> Result<Generator<P, R>, Error> {
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::InvalidOutputSize(self.width, self.height));
+        }
+        let image_sizes = [
+            (
+                "constraint image",
+                self.constraint_image
+                    .as_ref()
+                    .map(|image| image.dimensions()),
+            ),
+            (
+                "mask image",
+                self.mask_image.as_ref().map(|image| image.dimensions()),
+            ),
+        ];
+        for (name, size) in image_sizes {
+            if let Some((width, height)) = size {
+                if (width as usize, height as usize) != (self.width, self.height) {
+                    return Err(Error::InvalidConstraint(format!(
+                        "the {name} is {width}x{height}, the output is {}x{}",
+                        self.width, self.height
+                    )));
+                }
+            }
+        }
+        let (ruleset, quantize_report) = match self.source {
+            Source::Ruleset(ruleset) => (ruleset, None),
+            Source::Sample(sample) => {
+                ruleset::check_tile_size(&sample, self.tile_size)?;
+                let extract_tiles = |sample: &TileImage<P>| {
+                    tile::extract_tiles(
+                        sample,
+                        self.tile_size,
+                        self.border_style,
+                        self.symmetry,
+                        self.wildcards,
+                    )
+                };
+                let pattern_count = |sample: &TileImage<P>| {
+                    tile::count_patterns(sample, self.tile_size, self.border_style, self.symmetry)
+                };
+                let (tiles, quantize_report) = match self.quantization {
+                    Some(quantization) => {
+                        let (quantized, colors_before, colors_after) =
+                            quantize::quantize(&sample, quantization);
+                        let tiles = extract_tiles(&quantized);
+                        let report = QuantizeReport {
+                            colors_before,
+                            colors_after,
+                            patterns_before: pattern_count(&sample),
+                            patterns_after: pattern_count(&quantized),
+                        };
+                        (tiles, Some(report))
+                    }
+                    None => (extract_tiles(&sample), None),
+                };
+                let ruleset = Ruleset::from_tiles(tiles, self.tile_size, self.border_style);
+                (Arc::new(ruleset), quantize_report)
+            }
+        };
+        let tiles = ruleset.tiles();
+        let options = Domain::from_indexes(
+            tiles.len(),
+            tiles
+                .iter()
+                .enumerate()
+                .filter(|(_, tile)| tile.frequency != 0)
+                .map(|(i, _)| i),
+        );
+        if options.is_empty() {
+            return Err(Error::NoTiles);
+        }
+        let mut grid = Grid::new(
+            self.width,
+            self.height,
+            options,
+            tiles,
+            self.max_depth,
+            self.propagation,
+        )
+        .with_wrapping(self.wrapping);
+        let mask = self.mask.or_else(|| {
+            let image = self.mask_image?;
+            let (width, height) = (self.width, self.height);
+            Some(
+                (0..width * height)
+                    .map(|i| {
+                        let pixel = image.get_pixel((i % width) as u32, (i / width) as u32);
+                        pixel[0] >= 128 && pixel[1] >= 128
+                    })
+                    .collect(),
+            )
+        });
+        if let Some(mask) = mask {
+            if mask.len() != self.width * self.height {
+                return Err(Error::InvalidConstraint(format!(
+                    "the mask has {} cells, the output has {}",
+                    mask.len(),
+                    self.width * self.height
+                )));
+            }
+            grid = grid.with_mask(mask);
+        }
+        if let Some(max_backtracks) = self.max_backtracks {
+            grid = grid.with_backtracking(max_backtracks);
+        }
+        let mut generator = Generator {
+            grid,
+            heuristic: self.heuristic,
+            selector: self.selector,
+            max_attempts: self.max_attempts,
+            ruleset,
+            rng: self.rng,
+            seed: self.seed,
+            quantize_report,
+        };
+        for constraint in self.edge_constraints {
+            for &direction in constraint.edge.directions() {
+                let allowed_options = match constraint.source {
+                    EdgeSource::Sample => generator.tiles_where(|tile| tile.edges[direction]),
+                    EdgeSource::Color(color) => generator
+                        .tiles_where(|tile| tile.center_pixel().to_rgba8() == color.to_rgba()),
+                };
+                if allowed_options.is_empty() {
+                    return Err(Error::InvalidConstraint(match constraint.source {
+                        EdgeSource::Sample => {
+                            format!("no tile lies on the {direction:?} edge of the sample")
+                        }
+                        EdgeSource::Color(Rgb([r, g, b])) => {
+                            format!("no tile has the color #{r:02x}{g:02x}{b:02x}")
+                        }
+                    }));
+                }
+                generator.constrain_edge(direction, allowed_options);
+            }
+        }
+        if let Some(constraint_image) = self.constraint_image {
+            for (x, y, pixel) in constraint_image.enumerate_pixels() {
+                if pixel[3] == u8::MAX {
+                    generator.fix_color(x as usize, y as usize, pixel.to_rgb())?;
+                }
+            }
+        }
+        generator
+            .grid
+            .check_constraints(generator.ruleset.tiles())?;
+        Ok(generator)
+    }
+}
+
+impl<P: TilePixel, R: Rng> Generator<P, R> {
+    /// Collapses a single cell, returns `Ok(false)` once every cell has collapsed
+    pub fn step(&mut self) -> Result<bool, Error> {
+        self.grid.collapse(
+            self.ruleset.tiles(),
+            self.heuristic.as_mut(),
+            self.selector.as_mut(),
+            &mut self.rng,
+        )
+    }
+
+    pub fn regenerate(&mut self, increase_attempts: bool) {
+        self.grid.regenerate(increase_attempts)
+    }
+
+    /// Restarts after a contradiction, or fails with [`Error::AttemptLimit`] once the attempts are used up
+    pub fn retry(&mut self) -> Result<(), Error> {
+        let attempts = self.grid.attempts() as usize + 1;
+        if self.max_attempts.is_some_and(|max| attempts >= max) {
+            return Err(Error::AttemptLimit(attempts));
+        }
+        self.regenerate(true);
+        Ok(())
+    }
+
+    /// Limits the cell at `(x, y)` to tiles with the given opaque center color from the start of the next attempt.
+    /// Cells outside the grid are ignored, fails if no tile has the color
+    pub fn fix_color(&mut self, x: usize, y: usize, color: Rgb<u8>) -> Result<(), Error> {
+        if x >= self.grid.width() || y >= self.grid.height() {
+            return Ok(());
+        }
+        let allowed_options =
+            self.tiles_where(|tile| tile.center_pixel().to_rgba8() == color.to_rgba());
+        if allowed_options.is_empty() {
+            let Rgb([r, g, b]) = color;
+            return Err(Error::InvalidConstraint(format!(
+                "no tile has the color #{r:02x}{g:02x}{b:02x} of the cell at ({x}, {y})"
+            )));
+        }
+        self.grid
+            .constrain(x + y * self.grid.width(), allowed_options);
+        Ok(())
+    }
+
+    /// Limits every cell along the edge of the grid in the given direction to the allowed options
+    pub fn constrain_edge(&mut self, direction: Direction, allowed_options: Domain) {
+        let (width, height) = (self.grid.width(), self.grid.height());
+        let cells: Vec<usize> = match direction {
+            Direction::Up => (0..width).collect(),
+            Direction::Down => (0..width).map(|x| x + (height - 1) * width).collect(),
+            Direction::Left => (0..height).map(|y| y * width).collect(),
+            Direction::Right => (0..height).map(|y| width - 1 + y * width).collect(),
+        };
+        for grid_index in cells {
+            self.grid.constrain(grid_index, allowed_options.clone());
+        }
+    }
+
+    /// The tiles accepted by the filter, excluding merged duplicates
+    pub fn tiles_where(&self, filter: impl Fn(&Tile<P>) -> bool) -> Domain {
+        Domain::from_indexes(
+            self.tiles().len(),
+            self.tiles()
+                .iter()
+                .enumerate()
+                .filter(|(_, tile)| tile.frequency != 0 && filter(tile))
+                .map(|(i, _)| i),
+        )
+    }
+
+    /// Collapses the whole grid, restarting whenever a cell runs out of options until the attempts are used up
+    pub fn generate(&mut self) -> Result<TileImage<P::Output>, Error> {
+        loop {
+            match self.step() {
+                Ok(true) => (),
+                Ok(false) => {
+                    return Ok(self
+                        .to_image()
+                        .expect("Finished collapse must mean all cells have one option"))
+                }
+                Err(Error::Contradiction) => self.retry()?,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// The seed the rng was created from, if it was not provided directly
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// How much quantizing the sample reduced its colors and patterns, if it was quantized
+    pub fn quantize_report(&self) -> Option<&QuantizeReport> {
+        self.quantize_report.as_ref()
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn tiles(&self) -> &[Tile<P>] {
+        self.ruleset.tiles()
+    }
+
+    /// The patterns this generator collapses to, which can be saved or shared with other generators
+    pub fn ruleset(&self) -> &Arc<Ruleset<P>> {
+        &self.ruleset
+    }
+
+    /// Whether the collapsed output tiles seamlessly along every wrapped edge,
+    /// checking that the tiles on opposite edges are allowed next to each other. Cells outside the mask are skipped
+    pub fn is_seamless(&self) -> bool {
+        let grid = &self.grid;
+        let (width, height) = (grid.width(), grid.height());
+        let wrapping = grid.wrapping();
+        let mut pairs = Vec::new();
+        if wrapping.horizontal() {
+            pairs.extend((0..height).map(|y| (width - 1 + y * width, Direction::Right)));
+        }
+        if wrapping.vertical() {
+            pairs.extend((0..width).map(|x| (x + (height - 1) * width, Direction::Down)));
+        }
+        pairs.into_iter().all(|(grid_index, direction)| {
+            let collapsed = |grid_index| {
+                let mut options = grid.get_index(grid_index)?.options().iter();
+                match (options.next(), options.next()) {
+                    (Some(tile_index), None) => Some(tile_index),
+                    _ => None,
+                }
+            };
+            let Some(neighbor_index) = grid
+                .index_in_direction(grid_index, direction)
+                .filter(|_| grid.exists(grid_index))
+            else {
+                return true;
+            };
+            match (collapsed(grid_index), collapsed(neighbor_index)) {
+                (Some(tile_index), Some(neighbor_tile)) => {
+                    self.tiles()[tile_index].neighbors[direction].contains(neighbor_tile)
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Renders the center pixel of each cell's tile, leaving cells outside the mask transparent.
+    /// `None` if the grid has not fully collapsed
+    pub fn to_image(&self) -> Option<TileImage<P::Output>> {
+        self.render(Render::Center, 1)
+    }
+
+    /// Renders the collapsed grid in the given mode, upscaled by an integer factor.
+    /// `None` if the grid has not fully collapsed
+    pub fn render(&self, render: Render, scale: u32) -> Option<TileImage<P::Output>> {
+        render::render(&self.grid, self.tiles(), render, scale)
+    }
+}
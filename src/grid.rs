@@ -1,49 +1,185 @@
 use std::{
-    collections::HashSet,
+    collections::VecDeque,
+    mem,
     ops::{Index, IndexMut},
 };
 
-use rand::{distr::weighted::WeightedIndex, prelude::*};
+use clap::ValueEnum;
+use rand::Rng;
 
+pub mod backtrack;
 pub mod cell;
+pub mod support;
 
-use crate::tile::{Direction, Tile};
-use cell::{Cell, Exhausted};
+use crate::{
+    domain::Domain,
+    error::Error,
+    heuristic::SelectionHeuristic,
+    image_impls::TilePixel,
+    tile::{Direction, Tile},
+    value_selector::ValueSelector,
+};
+use backtrack::{Backtracking, Decision};
+use cell::Cell;
+use support::{Compatibility, Supports};
+
+/// How a change to a cell's options is spread to the rest of the grid
+#[derive(Copy, Clone, ValueEnum, Default)]
+pub enum Propagation {
+    /// Works through a queue of changed cells until no more options can be removed
+    #[default]
+    Full,
+    /// Recursively updates neighbors up to a maximum depth that grows with each failed attempt
+    DepthLimited,
+    /// Keeps a count of supporting neighbor tiles for every tile in every cell (AC-4),
+    /// removing a tile once any count reaches zero
+    SupportCount,
+}
+
+/// Which edges of the grid wrap around to the opposite edge, making the output tile seamlessly
+#[derive(Copy, Clone, ValueEnum, Default, PartialEq, Eq)]
+pub enum Wrapping {
+    #[default]
+    None,
+    /// The left and right edges are neighbors
+    Horizontal,
+    /// The top and bottom edges are neighbors
+    Vertical,
+    /// Both pairs of opposite edges are neighbors
+    Both,
+}
+
+impl Wrapping {
+    pub fn horizontal(&self) -> bool {
+        matches!(self, Wrapping::Horizontal | Wrapping::Both)
+    }
+
+    pub fn vertical(&self) -> bool {
+        matches!(self, Wrapping::Vertical | Wrapping::Both)
+    }
+}
 
 #[derive(Clone)]
 pub struct Grid {
     buf: Vec<Cell>,
     height: usize,
     width: usize,
-    options: HashSet<usize>,
+    template: Cell,
     attempts: u32,
     initial_max_depth: usize,
+    propagation: Propagation,
+    wrapping: Wrapping,
+    /// Which cells exist, cells outside the mask have no options and are never collapsed
+    mask: Option<Vec<bool>>,
+    backtracking: Option<Backtracking>,
+    compatibility: Option<Compatibility>,
+    supports: Option<Supports>,
+    /// Options each constrained cell is limited to at the start of every attempt
+    constraints: Vec<(usize, Domain)>,
+    /// Whether the current attempt has been prepared for its first collapse
+    started: bool,
+    /// Number of cells with more than one option
+    uncollapsed: usize,
+    /// Cells whose options changed since the last selection
+    changed: Vec<usize>,
 }
 
 impl Grid {
-    pub fn new(
+    pub fn new<P: TilePixel>(
         width: usize,
         height: usize,
-        options: HashSet<usize>,
+        options: Domain,
+        tiles: &[Tile<P>],
         initial_max_depth: usize,
+        propagation: Propagation,
     ) -> Grid {
-        let buf = (0..(width * height))
-            .map(|_| Cell::new(options.clone()))
-            .collect();
-        Self {
-            buf,
+        let compatibility = match propagation {
+            Propagation::SupportCount => Some(Compatibility::new(tiles, &options)),
+            _ => None,
+        };
+        let template = Cell::new(options, tiles);
+        let mut grid = Self {
+            buf: vec![template.clone(); width * height],
             height,
             width,
-            options,
+            template,
             initial_max_depth,
             attempts: 0,
+            propagation,
+            wrapping: Wrapping::None,
+            mask: None,
+            backtracking: None,
+            compatibility,
+            supports: None,
+            constraints: Vec::new(),
+            started: false,
+            uncollapsed: 0,
+            changed: Vec::new(),
+        };
+        grid.count_uncollapsed();
+        grid
+    }
+
+    /// Undo decisions that lead to a contradiction instead of failing, giving up after `max_backtracks`
+    pub fn with_backtracking(mut self, max_backtracks: usize) -> Grid {
+        self.backtracking = Some(Backtracking::new(max_backtracks));
+        self
+    }
+
+    /// Makes propagation continue across the wrapped edges of the grid
+    pub fn with_wrapping(mut self, wrapping: Wrapping) -> Grid {
+        self.wrapping = wrapping;
+        self
+    }
+
+    /// Limits the grid to the cells where the mask, in reading order, is `true`
+    pub fn with_mask(mut self, mask: Vec<bool>) -> Grid {
+        assert_eq!(
+            mask.len(),
+            self.buf.len(),
+            "mask must have one entry per cell"
+        );
+        self.mask = Some(mask);
+        self.apply_mask();
+        self.count_uncollapsed();
+        self
+    }
+
+    /// Whether the cell is inside the mask, always `true` without a mask
+    pub fn exists(&self, grid_index: usize) -> bool {
+        match &self.mask {
+            Some(mask) => mask.get(grid_index).copied().unwrap_or(false),
+            None => grid_index < self.buf.len(),
         }
     }
 
+    pub fn wrapping(&self) -> Wrapping {
+        self.wrapping
+    }
+
+    pub fn backtracking(&self) -> Option<&Backtracking> {
+        self.backtracking.as_ref()
+    }
+
+    /// Number of attempts that ran into a contradiction and were restarted with more attempts
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Limits a cell to the allowed options, applied and propagated at the start of every attempt
+    pub fn constrain(&mut self, grid_index: usize, allowed_options: Domain) {
+        self.constraints.push((grid_index, allowed_options));
+    }
+
     pub fn regenerate(&mut self, increase_attempts: bool) {
-        self.buf = (0..(self.width * self.height))
-            .map(|_| Cell::new(self.options.clone()))
-            .collect();
+        self.buf.fill(self.template.clone());
+        self.apply_mask();
+        self.started = false;
+        self.changed.clear();
+        self.count_uncollapsed();
+        if let Some(backtracking) = &mut self.backtracking {
+            backtracking.reset();
+        }
         if increase_attempts {
             self.attempts += 1;
         }
@@ -85,108 +221,318 @@ impl Grid {
         self.width
     }
 
-    pub fn collapse<T: Rng>(&mut self, tiles: &[Tile], rng: &mut T) -> Result<bool, Exhausted> {
-        {
-            let mut cells: Vec<(usize, &mut Cell)> = self
-                .buf
-                .iter_mut()
-                .enumerate()
-                .filter(|(_, cell)| cell.options.len() > 1)
-                .collect();
-
-            if cells.is_empty() {
-                return Ok(false);
-            }
-            // precalculate whether this will be the last cell to compute to that we don't need to filter the vector again.
-            let last_cell = cells.len() == 1;
-
-            let mut min = f64::MAX;
-            let mut min_indexes = Vec::new();
-            for (i, (_, cell)) in cells.iter().enumerate() {
-                let entropy = cell.calculate_entropy(tiles);
-                if entropy == min {
-                    min_indexes.push(i);
-                } else if entropy < min {
-                    min = entropy;
-                    min_indexes = vec![i];
+    /// Collapses the cell chosen by the heuristic to the option chosen by the selector, returns `Ok(false)` once every cell has collapsed
+    pub fn collapse<P: TilePixel, T: Rng>(
+        &mut self,
+        tiles: &[Tile<P>],
+        heuristic: &mut dyn SelectionHeuristic,
+        selector: &mut dyn ValueSelector<P>,
+        rng: &mut T,
+    ) -> Result<bool, Error> {
+        if !self.started {
+            self.start(tiles)?;
+            heuristic.reset(self, rng);
+            self.changed.clear();
+        }
+        let Some(grid_index) = heuristic.select(self, &self.changed, rng) else {
+            return Ok(false);
+        };
+        self.changed.clear();
+        let tile_index = selector
+            .choose(self, tiles, grid_index, rng)
+            .filter(|&tile_index| self.buf[grid_index].options().contains(tile_index))
+            .ok_or(Error::InvalidChoice(grid_index))?;
+        if let Some(backtracking) = &mut self.backtracking {
+            backtracking.decisions.push(Decision {
+                grid_index,
+                tile_index,
+                trail_len: backtracking.trail.len(),
+            });
+        }
+        self.restrict(
+            tiles,
+            grid_index,
+            &Domain::from_indexes(tiles.len(), [tile_index]),
+        )?;
+        if self.propagate(tiles, grid_index).is_err() {
+            self.backtrack(tiles)?;
+        }
+        Ok(self.uncollapsed > 0)
+    }
+
+    pub fn cells(&self) -> Cells<'_> {
+        Cells { grid: self, i: 0 }
+    }
+
+    /// The neighbor of a cell, wrapping around the edges of the grid that wrap and stopping at cells outside the mask
+    pub fn index_in_direction(&self, index: usize, direction: Direction) -> Option<usize> {
+        let (width, height) = (self.width(), self.height());
+        if index >= self.buf.len() {
+            return None;
+        }
+        let (x, y) = (index % width, index / width);
+        let (x, y) = match direction {
+            Direction::Up if y > 0 => (x, y - 1),
+            Direction::Up if self.wrapping.vertical() => (x, height - 1),
+            Direction::Down if y + 1 < height => (x, y + 1),
+            Direction::Down if self.wrapping.vertical() => (x, 0),
+            Direction::Left if x > 0 => (x - 1, y),
+            Direction::Left if self.wrapping.horizontal() => (width - 1, y),
+            Direction::Right if x + 1 < width => (x + 1, y),
+            Direction::Right if self.wrapping.horizontal() => (0, y),
+            _ => return None,
+        };
+        Some(x + y * width).filter(|&index| self.exists(index))
+    }
+
+    /// Applies and propagates the constraints without collapsing anything, leaving the grid ready for a new attempt.
+    /// Fails with [`Error::InvalidConstraint`] if they contradict each other, which no attempt could fix
+    pub fn check_constraints<P: TilePixel>(&mut self, tiles: &[Tile<P>]) -> Result<(), Error> {
+        let result = self.start(tiles);
+        self.regenerate(false);
+        result
+    }
+
+    /// Prepares the selection and propagation state at the start of an attempt.
+    /// Nothing has been chosen yet, so a contradiction here is reported as [`Error::InvalidConstraint`]
+    fn start<P: TilePixel>(&mut self, tiles: &[Tile<P>]) -> Result<(), Error> {
+        self.apply_constraints(tiles).map_err(|_| {
+            Error::InvalidConstraint("the constraints cannot all be satisfied at once".to_string())
+        })
+    }
+
+    fn apply_constraints<P: TilePixel>(&mut self, tiles: &[Tile<P>]) -> Result<(), Error> {
+        self.started = true;
+        if let Some(compatibility) = &self.compatibility {
+            let mut supports = Supports::new(self.buf.len(), compatibility, tiles.len());
+            for (grid_index, cell) in self.buf.iter().enumerate() {
+                for direction in Direction::ALL {
+                    if self.index_in_direction(grid_index, direction).is_none() {
+                        continue;
+                    }
+                    for tile_index in cell.options().iter() {
+                        if supports.is_unsupported(grid_index, tile_index, direction) {
+                            supports.pending.push((grid_index, tile_index));
+                        }
+                    }
                 }
             }
+            self.supports = Some(supports);
+            self.propagate_supports(tiles)?;
+        }
+        let constraints = mem::take(&mut self.constraints);
+        let result = constraints
+            .iter()
+            .try_for_each(|(grid_index, allowed_options)| {
+                if self.restrict(tiles, *grid_index, allowed_options)? {
+                    self.propagate(tiles, *grid_index)?;
+                }
+                Ok(())
+            });
+        self.constraints = constraints;
+        result
+    }
 
-            let (index, cell) = &mut cells[*min_indexes.choose(rng).expect("No cells in grid")];
-            let dist = WeightedIndex::new(
-                cell.options
-                    .iter()
-                    .map(|&tile_index| tiles[tile_index].frequency as usize),
-            )
-            .expect("This distribution should always succeed at being created");
-            let chosen_index = cell
-                .options
-                .drain()
-                .nth(dist.sample(rng))
-                .ok_or(Exhausted)?;
-            cell.options.insert(chosen_index);
-            if last_cell {
-                return Ok(false);
-            }
-            let (grid_index, options) = (*index, cell.options.clone());
-            self.update_neighbors(tiles, grid_index, options, 0)?;
+    fn apply_mask(&mut self) {
+        let Some(mask) = &self.mask else {
+            return;
         };
+        let empty = Cell::empty(self.template.options().size());
+        for (cell, _) in self.buf.iter_mut().zip(mask).filter(|(_, &exists)| !exists) {
+            *cell = empty.clone();
+        }
+    }
+
+    fn neighbor_indexes(&self, grid_index: usize) -> [Option<usize>; 4] {
+        Direction::ALL.map(|direction| self.index_in_direction(grid_index, direction))
+    }
+
+    fn count_uncollapsed(&mut self) {
+        self.uncollapsed = self
+            .buf
+            .iter()
+            .filter(|cell| cell.options().len() > 1)
+            .count();
+    }
+
+    /// Restricts a cell to the available options, returning whether any were removed
+    fn restrict<P: TilePixel>(
+        &mut self,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+        available_options: &Domain,
+    ) -> Result<bool, Error> {
+        let cell = &mut self.buf[grid_index];
+        let removed: Vec<usize> = cell.options().difference(available_options).collect();
+        if removed.is_empty() {
+            return Ok(false);
+        }
+        cell.update_options(available_options, tiles)?;
+        self.record_removed(grid_index, &removed);
         Ok(true)
     }
 
-    pub fn cells(&self) -> Cells<'_> {
-        Cells { grid: self, i: 0 }
+    /// Removes a single option from a cell, returning whether it was present
+    fn remove<P: TilePixel>(
+        &mut self,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+        tile_index: usize,
+    ) -> Result<bool, Error> {
+        let removed = self.buf[grid_index].remove(tile_index, tiles)?;
+        if removed {
+            self.record_removed(grid_index, &[tile_index]);
+        }
+        Ok(removed)
     }
 
-    pub fn index_in_direction(&self, index: usize, direction: Direction) -> Option<usize> {
-        match direction {
-            Direction::Up => index.checked_sub(self.width()),
-            Direction::Down => index.checked_add(self.width()),
-            Direction::Left => index
-                .checked_sub(1)
-                .take_if(|index| *index % self.width() != self.width() - 1),
-            Direction::Right => index
-                .checked_add(1)
-                .take_if(|index| *index % self.width() != 0),
+    /// Updates the trail, supports and changed cells after options were removed from a cell
+    fn record_removed(&mut self, grid_index: usize, removed: &[usize]) {
+        if let Some(backtracking) = &mut self.backtracking {
+            backtracking
+                .trail
+                .extend(removed.iter().map(|&tile_index| (grid_index, tile_index)));
         }
+        let neighbors = self.neighbor_indexes(grid_index);
+        if let (Some(supports), Some(compatibility)) = (&mut self.supports, &self.compatibility) {
+            for &tile_index in removed {
+                supports.remove(compatibility, neighbors, tile_index);
+            }
+        }
+        if self.buf[grid_index].options().len() == 1 {
+            self.uncollapsed -= 1;
+        }
+        self.changed.push(grid_index);
+    }
+
+    /// Undoes decisions until banning the failed tile from its cell no longer leads to a contradiction
+    fn backtrack<P: TilePixel>(&mut self, tiles: &[Tile<P>]) -> Result<(), Error> {
+        loop {
+            let Some(backtracking) = &mut self.backtracking else {
+                return Err(Error::Contradiction);
+            };
+            if backtracking.backtracks >= backtracking.max_backtracks {
+                return Err(Error::Contradiction);
+            }
+            let Some(decision) = backtracking.decisions.pop() else {
+                return Err(Error::Contradiction);
+            };
+            backtracking.backtracks += 1;
+            let mut restored = Vec::new();
+            let trail: Vec<(usize, usize)> =
+                backtracking.trail.drain(decision.trail_len..).collect();
+            for (grid_index, tile_index) in trail {
+                self.buf[grid_index].insert(tile_index, tiles);
+                if self.buf[grid_index].options().len() == 2 {
+                    self.uncollapsed += 1;
+                }
+                let neighbors = self.neighbor_indexes(grid_index);
+                if let (Some(supports), Some(compatibility)) =
+                    (&mut self.supports, &self.compatibility)
+                {
+                    supports.restore(compatibility, neighbors, tile_index);
+                }
+                restored.push(grid_index);
+            }
+            restored.sort_unstable();
+            restored.dedup();
+            self.changed.extend(restored);
+            let mut remaining = self.buf[decision.grid_index].options().clone();
+            remaining.remove(decision.tile_index);
+            if self
+                .restrict(tiles, decision.grid_index, &remaining)
+                .and_then(|_| self.propagate(tiles, decision.grid_index))
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    fn propagate<P: TilePixel>(
+        &mut self,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+    ) -> Result<(), Error> {
+        match self.propagation {
+            Propagation::Full => self.propagate_queue(tiles, grid_index),
+            Propagation::DepthLimited => {
+                let options = self.buf[grid_index].options().clone();
+                self.update_neighbors(tiles, grid_index, options, 0)
+            }
+            Propagation::SupportCount => self.propagate_supports(tiles),
+        }
+    }
+
+    /// Removes tiles that lost all support in a direction until none are left to remove
+    fn propagate_supports<P: TilePixel>(&mut self, tiles: &[Tile<P>]) -> Result<(), Error> {
+        while let Some((grid_index, tile_index)) = self
+            .supports
+            .as_mut()
+            .and_then(|supports| supports.pending.pop())
+        {
+            if self.remove(tiles, grid_index, tile_index).is_err() {
+                if let Some(supports) = &mut self.supports {
+                    supports.pending.clear();
+                }
+                return Err(Error::Contradiction);
+            }
+        }
+        Ok(())
+    }
+
+    /// Propagates changes until every cell only has options supported by all of its neighbors
+    fn propagate_queue<P: TilePixel>(
+        &mut self,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+    ) -> Result<(), Error> {
+        let mut queue = VecDeque::from([grid_index]);
+        let mut available_options = Domain::empty(tiles.len());
+        while let Some(grid_index) = queue.pop_front() {
+            for direction in Direction::ALL {
+                let Some(neighbor_index) = self.index_in_direction(grid_index, direction) else {
+                    continue;
+                };
+                available_options.clear();
+                for tile_index in self.buf[grid_index].options().iter() {
+                    available_options.union_with(&tiles[tile_index].neighbors[direction]);
+                }
+                if self.restrict(tiles, neighbor_index, &available_options)? {
+                    queue.push_back(neighbor_index);
+                }
+            }
+        }
+        Ok(())
     }
 
     fn max_depth(&self) -> usize {
         (self.initial_max_depth * 2usize.pow(self.attempts + 1)).min(self.width() + self.height())
     }
 
-    fn update_neighbors(
+    fn update_neighbors<P: TilePixel>(
         &mut self,
-        tiles: &[Tile],
+        tiles: &[Tile<P>],
         grid_index: usize,
-        options: HashSet<usize>,
+        options: Domain,
         mut depth: usize,
-    ) -> Result<(), Exhausted> {
+    ) -> Result<(), Error> {
         if depth > self.max_depth() {
             return Ok(());
         }
         let available_indexes = &options;
-        for direction in [
-            Direction::Up,
-            Direction::Down,
-            Direction::Left,
-            Direction::Right,
-        ] {
-            if let Some((neighbor_index, neighbor_cell)) = self
+        for direction in Direction::ALL {
+            if let Some(neighbor_index) = self
                 .index_in_direction(grid_index, direction)
-                .and_then(|index| self.get_index_mut(index).map(|cell| (index, cell)))
-                .take_if(|(_, neighbor_cell)| neighbor_cell.options.len() != 1)
+                .take_if(|index| self.buf[*index].options().len() != 1)
             {
-                let old_len = neighbor_cell.options.len();
-                let mut available_options = HashSet::with_capacity(tiles.len());
+                let mut available_options = Domain::empty(tiles.len());
                 for tile_index in available_indexes.iter() {
-                    available_options
-                        .extend(tiles[*tile_index].neighbors.borrow()[direction].iter());
+                    available_options.union_with(&tiles[tile_index].neighbors[direction]);
                 }
-                neighbor_cell.update_options(&available_options)?;
-                let new_len = neighbor_cell.options.len();
-                if old_len != new_len {
-                    let options = neighbor_cell.options.clone();
+                if self.restrict(tiles, neighbor_index, &available_options)? {
+                    let options = self.buf[neighbor_index].options().clone();
+                    let new_len = options.len();
                     if new_len != 1 {
                         depth += 1
                     }
@@ -0,0 +1,34 @@
+/// Records collapse decisions and the options they removed so they can be undone
+#[derive(Clone)]
+pub struct Backtracking {
+    pub max_backtracks: usize,
+    pub backtracks: usize,
+    pub(super) decisions: Vec<Decision>,
+    /// Every `(grid_index, tile_index)` removed since the first decision, in order of removal
+    pub(super) trail: Vec<(usize, usize)>,
+}
+
+#[derive(Clone, Copy)]
+pub(super) struct Decision {
+    pub grid_index: usize,
+    pub tile_index: usize,
+    /// Length of the trail before this decision was made
+    pub trail_len: usize,
+}
+
+impl Backtracking {
+    pub fn new(max_backtracks: usize) -> Backtracking {
+        Backtracking {
+            max_backtracks,
+            backtracks: 0,
+            decisions: Vec::new(),
+            trail: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.backtracks = 0;
+        self.decisions.clear();
+        self.trail.clear();
+    }
+}
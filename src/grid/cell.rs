@@ -1,88 +1,98 @@
-use std::collections::HashSet;
-
-use image::Pixel;
-use nannou::prelude::*;
-
 use super::Tile;
+use crate::{domain::Domain, error::Error, image_impls::TilePixel};
 
 #[derive(Clone)]
 pub struct Cell {
-    pub options: HashSet<usize>,
+    options: Domain,
+    /// Sum of the frequencies of every option, kept up to date as options are removed
+    sum_weights: f64,
+    /// Sum of `w * log2(w)` over the frequency `w` of every option
+    sum_weight_log_weights: f64,
 }
 
 impl Cell {
-    pub fn new(options: HashSet<usize>) -> Cell {
-        Cell { options }
+    pub fn new<P: TilePixel>(options: Domain, tiles: &[Tile<P>]) -> Cell {
+        let mut cell = Cell::empty(options.size());
+        for tile_index in options.iter() {
+            cell.insert(tile_index, tiles);
+        }
+        cell
     }
 
-    pub fn draw(&self, draw: &Draw, tiles: &[Tile], x: u32, y: u32, width: f32) {
-        if self.options.is_empty() {
-            draw.rect()
-                .x_y((x as f32 + 0.5) * width, (y as f32 + 0.5) * width)
-                .w_h(width, width)
-                .color(Rgb::from_components((255u8, 0u8, 100u8)))
-                .stroke_weight((width * 0.03).max(2.));
-        } else {
-            let mut sum_r = 0;
-            let mut sum_g = 0;
-            let mut sum_b = 0;
-            let mut count = 0;
-            for tile_index in self.options.iter() {
-                let tile = &tiles[*tile_index];
-                let image = &tile.image;
-                let frequency = tile.frequency;
-                let center_pixel = image.get_pixel(1, 1);
-                let [r, g, b] = center_pixel.channels() else {
-                    panic!("Wrong channel count!")
-                };
-                sum_r += *r as u32 * frequency;
-                sum_g += *g as u32 * frequency;
-                sum_b += *b as u32 * frequency;
-                count += frequency;
-            }
-            sum_r /= count;
-            sum_g /= count;
-            sum_b /= count;
-            draw.rect()
-                .x_y((x as f32 + 0.5) * width, (y as f32 + 0.5) * width)
-                .w_h(width, width)
-                .color(Rgb::from_components((
-                    sum_r as u8,
-                    sum_g as u8,
-                    sum_b as u8,
-                )));
-            // .stroke_weight((width * 0.03).max(2.));
+    /// A cell without any options, used for cells outside the mask
+    pub fn empty(size: usize) -> Cell {
+        Cell {
+            options: Domain::empty(size),
+            sum_weights: 0.,
+            sum_weight_log_weights: 0.,
         }
     }
 
-    /// Calculates the Shannon Entropy based on the provided list of tiles
-    pub fn calculate_entropy(&self, tiles: &[Tile]) -> f64 {
-        let mut tile_counts = vec![0u32; tiles.len()];
-        let mut total = 0;
-        for &tile_index in self.options.iter() {
-            let frequency = tiles[tile_index].frequency;
-            tile_counts[tile_index] += frequency;
-            total += frequency;
+    pub fn options(&self) -> &Domain {
+        &self.options
+    }
+
+    /// Calculates the Shannon Entropy of the remaining options, weighted by tile frequency
+    pub fn calculate_entropy(&self) -> f64 {
+        if self.sum_weights <= 0. {
+            return 0.;
         }
-        -tile_counts
-            .into_iter()
-            .filter(|&v| v != 0)
-            .map(|count: u32| {
-                let p = count as f64 / total as f64;
-                p * p.log2()
-            })
-            .sum::<f64>()
+        self.sum_weights.log2() - self.sum_weight_log_weights / self.sum_weights
     }
 
-    pub fn update_options(&mut self, available_options: &HashSet<usize>) -> Result<(), Exhausted> {
-        let new_options = &self.options & available_options;
-        if new_options.is_empty() {
-            Err(Exhausted)
-        } else {
-            self.options = new_options;
-            Ok(())
+    /// Removes any options that are not available, leaving the cell unchanged if none would remain
+    pub fn update_options<P: TilePixel>(
+        &mut self,
+        available_options: &Domain,
+        tiles: &[Tile<P>],
+    ) -> Result<(), Error> {
+        if self.options.intersection_len(available_options) == 0 {
+            return Err(Error::Contradiction);
+        }
+        for tile_index in self.options.difference(available_options) {
+            let weight = tiles[tile_index].frequency as f64;
+            self.sum_weights -= weight;
+            self.sum_weight_log_weights -= weight_log_weight(weight);
         }
+        self.options.intersect_with(available_options);
+        Ok(())
+    }
+
+    /// Removes a single option, returning whether it was present and leaving the cell unchanged if it was the last one
+    pub fn remove<P: TilePixel>(
+        &mut self,
+        tile_index: usize,
+        tiles: &[Tile<P>],
+    ) -> Result<bool, Error> {
+        if !self.options.contains(tile_index) {
+            return Ok(false);
+        }
+        if self.options.len() == 1 {
+            return Err(Error::Contradiction);
+        }
+        self.options.remove(tile_index);
+        let weight = tiles[tile_index].frequency as f64;
+        self.sum_weights -= weight;
+        self.sum_weight_log_weights -= weight_log_weight(weight);
+        Ok(true)
+    }
+
+    /// Adds an option back to the cell, returning whether it was missing
+    pub fn insert<P: TilePixel>(&mut self, tile_index: usize, tiles: &[Tile<P>]) -> bool {
+        let added = self.options.insert(tile_index);
+        if added {
+            let weight = tiles[tile_index].frequency as f64;
+            self.sum_weights += weight;
+            self.sum_weight_log_weights += weight_log_weight(weight);
+        }
+        added
     }
 }
 
-pub struct Exhausted;
+fn weight_log_weight(weight: f64) -> f64 {
+    if weight > 0. {
+        weight * weight.log2()
+    } else {
+        0.
+    }
+}
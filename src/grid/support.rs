@@ -0,0 +1,121 @@
+use crate::{
+    domain::Domain,
+    image_impls::TilePixel,
+    tile::{Direction, Tile},
+};
+
+/// The tiles allowed next to each tile in each direction, stored in one flat list
+#[derive(Clone)]
+pub struct Compatibility {
+    /// Start of each `(tile_index, direction)` range in `compatible`, with a final end marker
+    starts: Vec<usize>,
+    compatible: Vec<usize>,
+}
+
+/// Counts how many tiles in each neighboring cell still allow a tile, per cell, tile and direction
+#[derive(Clone)]
+pub struct Supports {
+    tile_count: usize,
+    counts: Vec<u32>,
+    /// `(grid_index, tile_index)` pairs that lost their last support and still need removing
+    pub(super) pending: Vec<(usize, usize)>,
+}
+
+impl Compatibility {
+    /// Builds the table from each tile's neighbors, limited to the tiles in `options`
+    pub fn new<P: TilePixel>(tiles: &[Tile<P>], options: &Domain) -> Compatibility {
+        let mut starts = Vec::with_capacity(tiles.len() * Direction::ALL.len() + 1);
+        let mut compatible = Vec::new();
+        for tile in tiles {
+            let neighbors = &tile.neighbors;
+            for direction in Direction::ALL {
+                starts.push(compatible.len());
+                compatible.extend(
+                    neighbors[direction]
+                        .iter()
+                        .filter(|&tile_index| options.contains(tile_index)),
+                );
+            }
+        }
+        starts.push(compatible.len());
+        Compatibility { starts, compatible }
+    }
+
+    pub fn get(&self, tile_index: usize, direction: Direction) -> &[usize] {
+        let i = tile_index * Direction::ALL.len() + direction as usize;
+        &self.compatible[self.starts[i]..self.starts[i + 1]]
+    }
+}
+
+impl Supports {
+    /// Every cell starts fully supported by neighbors holding all tiles
+    pub fn new(cell_count: usize, compatibility: &Compatibility, tile_count: usize) -> Supports {
+        let mut counts = Vec::with_capacity(cell_count * tile_count * Direction::ALL.len());
+        for _ in 0..cell_count {
+            for tile_index in 0..tile_count {
+                counts.extend(
+                    Direction::ALL
+                        .map(|direction| compatibility.get(tile_index, direction).len() as u32),
+                );
+            }
+        }
+        Supports {
+            tile_count,
+            counts,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Whether a tile has no support in the given direction
+    pub fn is_unsupported(
+        &self,
+        grid_index: usize,
+        tile_index: usize,
+        direction: Direction,
+    ) -> bool {
+        self.counts[self.index(grid_index, tile_index, direction)] == 0
+    }
+
+    /// Accounts for a tile removed from a cell, queuing tiles in the neighboring cells that lost their last support
+    pub fn remove(
+        &mut self,
+        compatibility: &Compatibility,
+        neighbors: [Option<usize>; 4],
+        tile_index: usize,
+    ) {
+        for (direction, neighbor_index) in Direction::ALL.into_iter().zip(neighbors) {
+            let Some(neighbor_index) = neighbor_index else {
+                continue;
+            };
+            for &neighbor_tile in compatibility.get(tile_index, direction) {
+                let i = self.index(neighbor_index, neighbor_tile, direction.opposing());
+                self.counts[i] -= 1;
+                if self.counts[i] == 0 {
+                    self.pending.push((neighbor_index, neighbor_tile));
+                }
+            }
+        }
+    }
+
+    /// Reverses [`Supports::remove`] for a tile restored to a cell
+    pub fn restore(
+        &mut self,
+        compatibility: &Compatibility,
+        neighbors: [Option<usize>; 4],
+        tile_index: usize,
+    ) {
+        for (direction, neighbor_index) in Direction::ALL.into_iter().zip(neighbors) {
+            let Some(neighbor_index) = neighbor_index else {
+                continue;
+            };
+            for &neighbor_tile in compatibility.get(tile_index, direction) {
+                let i = self.index(neighbor_index, neighbor_tile, direction.opposing());
+                self.counts[i] += 1;
+            }
+        }
+    }
+
+    fn index(&self, grid_index: usize, tile_index: usize, direction: Direction) -> usize {
+        (grid_index * self.tile_count + tile_index) * Direction::ALL.len() + direction as usize
+    }
+}
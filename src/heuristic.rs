@@ -0,0 +1,323 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use clap::ValueEnum;
+use rand::{seq::SliceRandom, Rng, RngCore};
+
+use crate::{
+    grid::{cell::Cell, Grid},
+    tile::Direction,
+};
+
+/// Chooses which uncollapsed cell of the grid is collapsed next
+pub trait SelectionHeuristic {
+    /// Called at the start of every attempt, before the first selection
+    fn reset(&mut self, grid: &Grid, rng: &mut dyn RngCore);
+
+    /// Picks an uncollapsed cell, `changed` lists the cells whose options changed since the last selection
+    fn select(&mut self, grid: &Grid, changed: &[usize], rng: &mut dyn RngCore) -> Option<usize>;
+}
+
+/// The built-in selection heuristics
+#[derive(Copy, Clone, ValueEnum, Default)]
+pub enum Heuristic {
+    /// The cell with the lowest Shannon entropy
+    #[default]
+    MinEntropy,
+    /// The cell with the fewest remaining options
+    MinRemainingValues,
+    /// Cells in reading order
+    Scanline,
+    /// Cells spiralling outwards from the centre of the grid
+    Spiral,
+    /// Cells in a random order
+    Random,
+    /// The lowest entropy cell next to an already collapsed cell
+    Frontier,
+}
+
+impl Heuristic {
+    pub fn build(&self) -> Box<dyn SelectionHeuristic + Send> {
+        match self {
+            Heuristic::MinEntropy => Box::new(MinEntropy::default()),
+            Heuristic::MinRemainingValues => Box::new(MinRemainingValues::default()),
+            Heuristic::Scanline => Box::new(Scanline::default()),
+            Heuristic::Spiral => Box::new(Spiral::default()),
+            Heuristic::Random => Box::new(RandomOrder::default()),
+            Heuristic::Frontier => Box::new(Frontier::default()),
+        }
+    }
+}
+
+impl<H: SelectionHeuristic + ?Sized> SelectionHeuristic for Box<H> {
+    fn reset(&mut self, grid: &Grid, rng: &mut dyn RngCore) {
+        (**self).reset(grid, rng)
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize], rng: &mut dyn RngCore) -> Option<usize> {
+        (**self).select(grid, changed, rng)
+    }
+}
+
+/// Selects the cell with the lowest entropy, breaking ties at random
+#[derive(Default)]
+pub struct MinEntropy(CostHeap);
+
+/// Selects the cell with the fewest options, breaking ties at random
+#[derive(Default)]
+pub struct MinRemainingValues(CostHeap);
+
+/// Selects the first uncollapsed cell in reading order
+#[derive(Default)]
+pub struct Scanline(Ordered);
+
+/// Selects cells in rings around the centre of the grid
+#[derive(Default)]
+pub struct Spiral(Ordered);
+
+/// Selects cells in an order shuffled at the start of each attempt
+#[derive(Default)]
+pub struct RandomOrder(Ordered);
+
+/// Grows the output outwards from the first collapsed cell, selecting the lowest entropy cell on its border
+#[derive(Default)]
+pub struct Frontier(CostHeap);
+
+impl SelectionHeuristic for MinEntropy {
+    fn reset(&mut self, grid: &Grid, rng: &mut dyn RngCore) {
+        self.0.reset(grid, rng, Cell::calculate_entropy);
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize], _: &mut dyn RngCore) -> Option<usize> {
+        self.0.select(grid, changed, Cell::calculate_entropy)
+    }
+}
+
+impl SelectionHeuristic for MinRemainingValues {
+    fn reset(&mut self, grid: &Grid, rng: &mut dyn RngCore) {
+        self.0.reset(grid, rng, remaining_values);
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize], _: &mut dyn RngCore) -> Option<usize> {
+        self.0.select(grid, changed, remaining_values)
+    }
+}
+
+impl SelectionHeuristic for Scanline {
+    fn reset(&mut self, grid: &Grid, _: &mut dyn RngCore) {
+        self.0.reset((0..grid.width() * grid.height()).collect());
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize], _: &mut dyn RngCore) -> Option<usize> {
+        self.0.select(grid, changed)
+    }
+}
+
+impl SelectionHeuristic for Spiral {
+    fn reset(&mut self, grid: &Grid, _: &mut dyn RngCore) {
+        let (width, height) = (grid.width(), grid.height());
+        let centre_x = (width as f64 - 1.) / 2.;
+        let centre_y = (height as f64 - 1.) / 2.;
+        let ring_and_angle = |index: usize| {
+            let dx = (index % width) as f64 - centre_x;
+            let dy = (index / width) as f64 - centre_y;
+            (dx.abs().max(dy.abs()), dy.atan2(dx))
+        };
+        let mut order: Vec<usize> = (0..width * height).collect();
+        order.sort_by(|&a, &b| {
+            let (a, b) = (ring_and_angle(a), ring_and_angle(b));
+            a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+        });
+        self.0.reset(order);
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize], _: &mut dyn RngCore) -> Option<usize> {
+        self.0.select(grid, changed)
+    }
+}
+
+impl SelectionHeuristic for RandomOrder {
+    fn reset(&mut self, grid: &Grid, rng: &mut dyn RngCore) {
+        let mut order: Vec<usize> = (0..grid.width() * grid.height()).collect();
+        order.shuffle(rng);
+        self.0.reset(order);
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize], _: &mut dyn RngCore) -> Option<usize> {
+        self.0.select(grid, changed)
+    }
+}
+
+impl SelectionHeuristic for Frontier {
+    fn reset(&mut self, grid: &Grid, rng: &mut dyn RngCore) {
+        self.0.noise = random_noise(grid, rng);
+        self.0.heap.clear();
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize], _: &mut dyn RngCore) -> Option<usize> {
+        let on_frontier = |grid_index: usize| {
+            Direction::ALL.into_iter().any(|direction| {
+                grid.index_in_direction(grid_index, direction)
+                    .and_then(|index| grid.get_index(index))
+                    .is_some_and(|cell| cell.options().len() == 1)
+            })
+        };
+        for &grid_index in changed {
+            self.0.push(grid, grid_index, Cell::calculate_entropy);
+            for direction in Direction::ALL {
+                if let Some(neighbor_index) = grid.index_in_direction(grid_index, direction) {
+                    self.0.push(grid, neighbor_index, Cell::calculate_entropy);
+                }
+            }
+        }
+        if let Some(grid_index) = self.0.pop_valid(grid, Cell::calculate_entropy, on_frontier) {
+            return Some(grid_index);
+        }
+        // nothing is collapsed yet, so start from the cell with the least noise
+        (0..grid.width() * grid.height())
+            .filter(|&grid_index| {
+                grid.get_index(grid_index)
+                    .is_some_and(|cell| cell.options().len() > 1)
+            })
+            .min_by(|&a, &b| self.0.noise[a].total_cmp(&self.0.noise[b]))
+    }
+}
+
+fn remaining_values(cell: &Cell) -> f64 {
+    cell.options().len() as f64
+}
+
+/// A little noise per cell to break ties between cells of equal cost at random
+fn random_noise(grid: &Grid, rng: &mut dyn RngCore) -> Vec<f64> {
+    (0..grid.width() * grid.height())
+        .map(|_| rng.random::<f64>() * 1e-6)
+        .collect()
+}
+
+/// A min-heap of cells by cost, where entries are checked against the cell when popped
+#[derive(Default)]
+struct CostHeap {
+    heap: BinaryHeap<CostEntry>,
+    noise: Vec<f64>,
+}
+
+/// A cell's cost when it was pushed to the heap, ordered so that the lowest priority is popped first
+struct CostEntry {
+    priority: f64,
+    cost: f64,
+    grid_index: usize,
+}
+
+impl CostHeap {
+    fn reset(&mut self, grid: &Grid, rng: &mut dyn RngCore, cost: fn(&Cell) -> f64) {
+        self.noise = random_noise(grid, rng);
+        self.heap.clear();
+        for grid_index in 0..grid.width() * grid.height() {
+            self.push(grid, grid_index, cost);
+        }
+    }
+
+    fn push(&mut self, grid: &Grid, grid_index: usize, cost: fn(&Cell) -> f64) {
+        if let Some(cell) = grid
+            .get_index(grid_index)
+            .filter(|cell| cell.options().len() > 1)
+        {
+            let cost = cost(cell);
+            self.heap.push(CostEntry {
+                priority: cost + self.noise[grid_index],
+                cost,
+                grid_index,
+            });
+        }
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize], cost: fn(&Cell) -> f64) -> Option<usize> {
+        for &grid_index in changed {
+            self.push(grid, grid_index, cost);
+        }
+        self.pop_valid(grid, cost, |_| true)
+    }
+
+    /// The lowest cost uncollapsed cell accepted by the filter, discarding outdated entries
+    fn pop_valid(
+        &mut self,
+        grid: &Grid,
+        cost: fn(&Cell) -> f64,
+        filter: impl Fn(usize) -> bool,
+    ) -> Option<usize> {
+        while let Some(entry) = self.heap.pop() {
+            let Some(cell) = grid.get_index(entry.grid_index) else {
+                continue;
+            };
+            if cell.options().len() > 1 && cost(cell) == entry.cost && filter(entry.grid_index) {
+                return Some(entry.grid_index);
+            }
+        }
+        None
+    }
+}
+
+impl PartialEq for CostEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CostEntry {}
+
+impl PartialOrd for CostEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CostEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .total_cmp(&self.priority)
+            .then_with(|| other.grid_index.cmp(&self.grid_index))
+    }
+}
+
+/// Selects the first uncollapsed cell in a fixed order
+#[derive(Default)]
+struct Ordered {
+    order: Vec<usize>,
+    /// Position of each cell in `order`
+    positions: Vec<usize>,
+    /// Every cell before this position in `order` has collapsed
+    cursor: usize,
+}
+
+impl Ordered {
+    fn reset(&mut self, order: Vec<usize>) {
+        self.positions = vec![0; order.len()];
+        for (position, &grid_index) in order.iter().enumerate() {
+            self.positions[grid_index] = position;
+        }
+        self.order = order;
+        self.cursor = 0;
+    }
+
+    fn select(&mut self, grid: &Grid, changed: &[usize]) -> Option<usize> {
+        // backtracking can uncollapse cells that were already passed
+        for &grid_index in changed {
+            if grid
+                .get_index(grid_index)
+                .is_some_and(|cell| cell.options().len() > 1)
+            {
+                self.cursor = self.cursor.min(self.positions[grid_index]);
+            }
+        }
+        while let Some(&grid_index) = self.order.get(self.cursor) {
+            if grid
+                .get_index(grid_index)
+                .is_some_and(|cell| cell.options().len() > 1)
+            {
+                return Some(grid_index);
+            }
+            self.cursor += 1;
+        }
+        None
+    }
+}
@@ -1,7 +1,53 @@
 use std::ops::Deref;
 
 use clap::ValueEnum;
-use image::{GenericImageView, ImageBuffer, Pixel, SubImage};
+use image::{imageops, GenericImageView, ImageBuffer, Pixel, Primitive, Rgba, SubImage};
+use num_traits::{ToPrimitive, Zero};
+
+/// A pixel type tiles can be extracted from, such as `Rgba<u8>`, `Luma<u8>` or `Rgb<u16>`
+pub trait TilePixel: Pixel<Subpixel: Send + Sync> + PartialEq + Send + Sync + 'static {
+    /// The pixel outputs are rendered with, RGBA with the same subpixel so that alpha and bit depth are kept
+    type Output: Pixel<Subpixel = Self::Subpixel> + 'static;
+
+    fn to_output(&self) -> Self::Output;
+
+    /// A fully transparent output pixel, used for cells outside the mask
+    fn transparent() -> Self::Output;
+
+    /// Converts to 8-bit RGBA, used for drawing and for comparing against colors
+    fn to_rgba8(&self) -> Rgba<u8>;
+
+    fn is_transparent(&self) -> bool;
+}
+
+impl<P> TilePixel for P
+where
+    P: Pixel<Subpixel: Send + Sync> + PartialEq + Send + Sync + 'static,
+    Rgba<P::Subpixel>: Pixel<Subpixel = P::Subpixel>,
+{
+    type Output = Rgba<P::Subpixel>;
+
+    fn to_output(&self) -> Self::Output {
+        self.to_rgba()
+    }
+
+    fn transparent() -> Self::Output {
+        Rgba([P::Subpixel::zero(); 4])
+    }
+
+    fn to_rgba8(&self) -> Rgba<u8> {
+        let max = P::Subpixel::DEFAULT_MAX_VALUE.to_f64().unwrap_or(1.);
+        Rgba(self.to_rgba().0.map(|channel| {
+            (channel.to_f64().unwrap_or(0.) / max * 255.)
+                .round()
+                .clamp(0., 255.) as u8
+        }))
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.to_rgba()[3].is_zero()
+    }
+}
 
 pub trait Tilable: Sized {
     fn tiles(&self, tile_size: u32, border_style: BorderStyle) -> Tiles<'_, Self>;
@@ -23,6 +69,57 @@ impl BorderStyle {
     }
 }
 
+/// Which rotated and reflected copies of each tile are added to the extracted tiles
+#[derive(Copy, Clone, ValueEnum, Default)]
+pub enum Symmetry {
+    #[default]
+    None,
+    /// The tile and its reflection along the x axis
+    MirrorX,
+    /// The tile and its reflection along the y axis
+    MirrorY,
+    /// All four quarter turn rotations of the tile
+    Rotations,
+    /// All rotations and reflections of the tile
+    Dihedral,
+}
+
+impl Symmetry {
+    pub fn variants<P>(
+        &self,
+        image: ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> Vec<ImageBuffer<P, Vec<P::Subpixel>>>
+    where
+        P: Pixel + 'static,
+    {
+        match self {
+            Symmetry::None => vec![image],
+            Symmetry::MirrorX => {
+                let mirrored = imageops::flip_horizontal(&image);
+                vec![image, mirrored]
+            }
+            Symmetry::MirrorY => {
+                let mirrored = imageops::flip_vertical(&image);
+                vec![image, mirrored]
+            }
+            Symmetry::Rotations => {
+                let rot90 = imageops::rotate90(&image);
+                let rot180 = imageops::rotate180(&image);
+                let rot270 = imageops::rotate270(&image);
+                vec![image, rot90, rot180, rot270]
+            }
+            Symmetry::Dihedral => Symmetry::Rotations
+                .variants(image)
+                .into_iter()
+                .flat_map(|rotation| {
+                    let mirrored = imageops::flip_horizontal(&rotation);
+                    [rotation, mirrored]
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct LoopingSubImage<I> {
     image: I,
@@ -89,6 +186,22 @@ where
     I: Deref,
     I::Target: GenericImageView + Sized,
 {
+    /// Position of the view's top left pixel in the underlying image, before any looping or clamping
+    pub fn offsets(&self) -> (i64, i64) {
+        (self.xoffset, self.yoffset)
+    }
+
+    /// Whether both views hold the same pixels, with `wildcards` fully transparent pixels match any pixel
+    pub fn matches(&self, other: &Self, wildcards: bool) -> bool
+    where
+        DerefPixel<I>: TilePixel,
+    {
+        self.dimensions() == other.dimensions()
+            && self.pixels().zip(other.pixels()).all(|((.., l), (.., r))| {
+                l == r || wildcards && (l.is_transparent() || r.is_transparent())
+            })
+    }
+
     pub fn to_image(&self) -> ImageBuffer<DerefPixel<I>, Vec<DerefSubpixel<I>>> {
         let mut out = ImageBuffer::new(self.width, self.height);
 
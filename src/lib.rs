@@ -0,0 +1,16 @@
+pub mod constraint;
+pub mod domain;
+pub mod error;
+pub mod generator;
+pub mod grid;
+pub mod heuristic;
+pub mod image_impls;
+pub mod parallel;
+pub mod quantize;
+pub mod render;
+pub mod ruleset;
+pub mod tile;
+pub mod value_selector;
+
+pub use error::Error;
+pub use generator::{Generator, GeneratorBuilder};
@@ -1,184 +1,539 @@
-use std::path::PathBuf;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
 
-use clap::Parser;
-use image::{self, EncodableLayout, ExtendedColorType, ImageBuffer};
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use image::{
+    self, DynamicImage, GrayAlphaImage, ImageDecoder, ImageReader, ImageResult, RgbaImage,
+};
 use nannou::prelude::*;
-use rand::{rngs::ThreadRng, Rng};
 
 mod args;
-mod grid;
-mod image_impls;
-mod tile;
 
 use args::Args;
-use grid::{cell::Exhausted, Grid};
-use image_impls::Tilable;
-use tile::{Direction, Tile};
+use wfc_overlapping_model::{
+    grid::{cell::Cell, Wrapping},
+    image_impls::TilePixel,
+    parallel,
+    quantize::Quantization,
+    render::Render,
+    ruleset::{Ruleset, RulesetHeader},
+    tile::{Tile, TileImage},
+    Error, Generator, GeneratorBuilder,
+};
+
+/// Output file names of a batch when `--output` is not given
+const DEFAULT_OUTPUT_TEMPLATE: &str = "out_{index}_{seed}.png";
 
 fn main() {
     let args = Args::parse();
-    if args.display {
-        nannou::app(|_| model())
-            .update(|_, model, _| update(model))
-            .simple_window(view)
-            .run();
+    let sixteen_bit = match (&args.ruleset, &args.input) {
+        (Some(ruleset), _) => File::open(ruleset)
+            .and_then(|file| RulesetHeader::read(BufReader::new(file)))
+            .is_ok_and(|header| header.max_value > u8::MAX as f64),
+        (None, Some(input)) => ImageReader::open(input)
+            .and_then(|reader| reader.with_guessed_format())
+            .ok()
+            .and_then(|reader| reader.into_decoder().ok())
+            .is_some_and(|decoder| {
+                let color = decoder.color_type();
+                color.bytes_per_pixel() > color.channel_count()
+            }),
+        (None, None) => false,
+    };
+    let result = Settings::new(args).and_then(|settings| {
+        if sixteen_bit {
+            run::<image::Rgba<u16>>(settings)
+        } else {
+            run::<image::Rgba<u8>>(settings)
+        }
+    });
+    if let Err(error) = result {
+        fail(error);
+    }
+}
+
+/// Prints the error and exits with its exit code, see [`exit_code`]
+fn fail(error: Error) -> ! {
+    eprintln!("error: {error}");
+    process::exit(exit_code(&error))
+}
+
+/// A distinct exit code for each kind of error, starting after the 2 that clap exits with on a usage error.
+/// A panic exits with 101
+fn exit_code(error: &Error) -> i32 {
+    match error {
+        Error::InvalidTileSize(_) => 3,
+        Error::UnreadableInput { .. } => 4,
+        Error::UnwritableOutput { .. } => 5,
+        Error::Contradiction => 6,
+        Error::AttemptLimit(_) => 7,
+        Error::InvalidConstraint(_) => 8,
+        Error::InvalidChoice(_) => 9,
+        Error::InvalidOutputSize(..) => 10,
+        Error::NoTiles => 11,
+    }
+}
+
+fn open_image(path: &Path) -> Result<DynamicImage, Error> {
+    image::open(path).map_err(|error| Error::UnreadableInput {
+        path: path.to_path_buf(),
+        source: error.into(),
+    })
+}
+
+/// Pixel types the CLI loads samples as, keeping the alpha channel and 16-bit samples at full depth
+trait SamplePixel: TilePixel {
+    fn load(image: DynamicImage) -> TileImage<Self>;
+
+    fn save(image: &TileImage<Self::Output>, path: &Path) -> ImageResult<()>;
+}
+
+impl SamplePixel for image::Rgba<u8> {
+    fn load(image: DynamicImage) -> TileImage<Self> {
+        image.into_rgba8()
+    }
+
+    fn save(image: &TileImage<Self::Output>, path: &Path) -> ImageResult<()> {
+        image.save(path)
+    }
+}
+
+impl SamplePixel for image::Rgba<u16> {
+    fn load(image: DynamicImage) -> TileImage<Self> {
+        image.into_rgba16()
+    }
+
+    fn save(image: &TileImage<Self::Output>, path: &Path) -> ImageResult<()> {
+        image.save(path)
+    }
+}
+
+/// The settings of a displayed run, handed to nannou's model function, which cannot capture them
+static DISPLAY_SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+
+fn run<P: SamplePixel>(settings: Settings) -> Result<(), Error> {
+    if settings.args.display {
+        *DISPLAY_SETTINGS.lock().unwrap() = Some(settings);
+        nannou::app(|_| {
+            let settings = DISPLAY_SETTINGS.lock().unwrap().take();
+            model::<P>(&settings.expect("Settings should be set before the app starts"))
+                .unwrap_or_else(|error| fail(error))
+        })
+        .update(|_, model, _| {
+            if let Err(error) = update(model) {
+                fail(error)
+            }
+        })
+        .simple_window(view::<P>)
+        .run();
+        Ok(())
+    } else if let Some(count) = settings.args.count {
+        batch::<P>(&settings, count)
     } else {
-        let mut model = model();
+        let mut model = model::<P>(&settings)?;
         loop {
-            update(&mut model);
+            update(&mut model)?;
             if !model.collapsing {
-                return;
+                return Ok(());
             }
         }
     }
 }
 
-struct Model<T> {
-    tiles: Vec<Tile>,
-    grid: Grid,
-    rng: T,
+struct Model<P: TilePixel> {
+    generator: Generator<P>,
     collapsing: bool,
     repeating: bool,
     output: Option<PathBuf>,
+    render: Render,
+    scale: u32,
 }
 
-fn model() -> Model<ThreadRng> {
-    let args = Args::parse();
-    let tile_size = args.tile_size;
-    if tile_size % 2 != 1 {
-        panic!("tile size must be odd")
-    }
-    let image = image::open(args.input).unwrap().into_rgb8();
-    let border_style = args.border_style;
-    let mut tiles: Vec<Tile> = image
-        .tiles(tile_size, border_style)
-        .map(|tile_view| Tile {
-            image: tile_view.to_image(),
-            neighbors: Default::default(),
-            frequency: 0,
-            border_style,
+/// The command line arguments with the images they point to loaded, shared by every generator of a run
+struct Settings {
+    args: Args,
+    constraint_image: Option<RgbaImage>,
+    mask: Option<GrayAlphaImage>,
+}
+
+impl Settings {
+    fn new(args: Args) -> Result<Settings, Error> {
+        let constraint_image = match &args.constraint_image {
+            Some(path) => Some(open_image(path)?.into_rgba8()),
+            None => None,
+        };
+        let mask = match &args.mask {
+            Some(path) => Some(open_image(path)?.into_luma_alpha8()),
+            None => None,
+        };
+        Ok(Settings {
+            args,
+            constraint_image,
+            mask,
         })
-        .collect();
-
-    for outer_index in 0..tiles.len() {
-        let outer_tile = &tiles[outer_index];
-        let original_index = tiles
-            .iter()
-            .enumerate()
-            .find(|(_, tile)| tile.image == outer_tile.image)
-            .expect("Will find itself if nothing else")
-            .0;
-        let original = original_index == outer_index;
-        tiles[original_index].frequency += 1;
-        let original_tile = &tiles[original_index];
-        let mut neighbors = original_tile.neighbors.borrow_mut();
-        if original {
-            for dir in [Direction::Up, Direction::Right] {
-                let opp_dir = dir.opposing();
-                if original_tile.view_in_direction(dir) == original_tile.view_in_direction(opp_dir)
-                {
-                    neighbors[dir].insert(outer_index);
-                    neighbors[opp_dir].insert(outer_index);
-                }
+    }
+
+    /// A builder for the input image or ruleset
+    fn source<P: SamplePixel>(&self) -> Result<GeneratorBuilder<P>, Error> {
+        match (&self.args.ruleset, &self.args.input) {
+            (Some(path), _) => {
+                let ruleset = File::open(path)
+                    .and_then(|file| Ruleset::<P>::load(BufReader::new(file)))
+                    .map_err(|error| Error::UnreadableInput {
+                        path: path.clone(),
+                        source: error.into(),
+                    })?;
+                Ok(GeneratorBuilder::from_ruleset(ruleset))
             }
+            (None, Some(input)) => Ok(GeneratorBuilder::new(P::load(open_image(input)?))),
+            (None, None) => unreachable!("clap requires an input or a ruleset"),
         }
-        for (inner_index, inner_tile) in tiles.iter().enumerate().skip(outer_index + 1) {
-            let mut inner_neighbors = inner_tile.neighbors.borrow_mut();
-            for dir in [
-                Direction::Up,
-                Direction::Down,
-                Direction::Left,
-                Direction::Right,
-            ] {
-                let opp_dir = dir.opposing();
-                let outer_view = original_tile.view_in_direction(dir);
-                let inner_view = inner_tile.view_in_direction(opp_dir);
-                if inner_view == outer_view {
-                    if original {
-                        neighbors[dir].insert(inner_index);
-                    }
-                    inner_neighbors[opp_dir].insert(original_index);
+    }
+
+    fn configure<P: TilePixel>(
+        &self,
+        builder: GeneratorBuilder<P>,
+        seed: u64,
+    ) -> GeneratorBuilder<P> {
+        let args = &self.args;
+        let mut builder = builder
+            .seed(seed)
+            .heuristic(args.heuristic.build())
+            .value_selector(args.value_selector.build(args.temperature));
+        for &constraint in &args.edge_constraints {
+            builder = builder.edge_constraint(constraint);
+        }
+        if let Some(constraint_image) = &self.constraint_image {
+            builder = builder.constraint_image(constraint_image.clone());
+        }
+        if let Some(mask) = &self.mask {
+            builder = builder.mask_image(mask.clone());
+        }
+        if let Some(colors) = args.colors {
+            builder = builder.quantize(Quantization::Palette(colors));
+        }
+        if let Some(tolerance) = args.tolerance {
+            builder = builder.quantize(Quantization::Tolerance(tolerance));
+        }
+        if args.backtrack {
+            builder = builder.backtracking(args.max_backtracks);
+        }
+        if let Some(max_attempts) = args.max_attempts {
+            builder = builder.max_attempts(max_attempts);
+        }
+        builder
+            .tile_size(args.tile_size)
+            .border_style(args.border_style)
+            .symmetry(args.symmetry)
+            .transparent_wildcards(args.wildcards)
+            .output_size(
+                args.output_width,
+                args.output_height.unwrap_or(args.output_width),
+            )
+            .max_depth(args.max_depth)
+            .propagation(args.propagation)
+            .wrapping(args.wrap)
+    }
+
+    /// Builds the first generator of the run, extracting the tiles that every later generator reuses
+    fn first_generator<P: SamplePixel>(&self, seed: u64) -> Result<Generator<P>, Error> {
+        let generator = self.configure(self.source()?, seed).build()?;
+        if let Some(report) = generator.quantize_report() {
+            println!(
+                "quantized {} colors to {}, patterns: {} -> {} ({} saved)",
+                report.colors_before,
+                report.colors_after,
+                report.patterns_before,
+                report.patterns_after,
+                report.patterns_saved()
+            );
+        }
+        if let Some(path) = &self.args.save_ruleset {
+            File::create(path)
+                .and_then(|file| generator.ruleset().save(BufWriter::new(file)))
+                .map_err(|error| Error::UnwritableOutput {
+                    path: path.clone(),
+                    source: error.into(),
+                })?;
+        }
+        Ok(generator)
+    }
+
+    /// A generator reusing the ruleset of an earlier generator
+    fn generator<P: SamplePixel>(
+        &self,
+        ruleset: &Arc<Ruleset<P>>,
+        seed: u64,
+    ) -> Result<Generator<P>, Error> {
+        self.configure(GeneratorBuilder::from_ruleset(ruleset.clone()), seed)
+            .build()
+    }
+
+    /// Collapses a generator for the seed, returning it with the seed it succeeded with and its number of attempts.
+    /// Uses parallel attempts when asked to, which may succeed with a derived seed
+    fn solve<P: SamplePixel>(
+        &self,
+        ruleset: &Arc<Ruleset<P>>,
+        seed: u64,
+        threads: Option<usize>,
+    ) -> Result<(Generator<P>, u64, usize), Error> {
+        let attempts = self.args.attempts;
+        if attempts > 1 {
+            let build = |seed| self.generator(ruleset, seed);
+            let solution = parallel::solve_parallel(build, seed, attempts, threads)?;
+            Ok((solution.generator, solution.seed, solution.attempt + 1))
+        } else {
+            let mut generator = self.generator(ruleset, seed)?;
+            loop {
+                match generator.step() {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(Error::Contradiction) => generator.retry()?,
+                    Err(error) => return Err(error),
                 }
             }
+            let attempts = generator.grid().attempts() as usize + 1;
+            Ok((generator, seed, attempts))
         }
     }
-    let options = tiles
-        .iter()
-        .enumerate()
-        .filter(|(_, tile)| tile.frequency != 0)
-        .map(|(i, _)| i)
-        .collect();
-    Model {
-        grid: Grid::new(
-            args.output_width,
-            args.output_height.unwrap_or(args.output_width),
-            options,
-            args.max_depth,
-        ),
-        tiles,
-        rng: rand::rng(),
+}
+
+fn model<P: SamplePixel>(settings: &Settings) -> Result<Model<P>, Error> {
+    let args = &settings.args;
+    let seed = args.seed.unwrap_or_else(rand::random);
+    println!("seed: {seed}");
+    let mut generator = settings.first_generator(seed)?;
+    if args.attempts > 1 {
+        let ruleset = generator.ruleset().clone();
+        let (solution, seed, attempt) = settings.solve(&ruleset, seed, args.threads)?;
+        println!(
+            "attempt {} of {} succeeded, seed: {}",
+            attempt, args.attempts, seed
+        );
+        generator = solution;
+    }
+    Ok(Model {
+        generator,
         collapsing: true,
         repeating: args.repeating,
-        output: args.output,
+        output: args.output.clone(),
+        render: args.render,
+        scale: args.scale,
+    })
+}
+
+/// Generates `count` outputs from the tiles of a single extraction, named after the output template.
+/// Outputs that fail are reported and skipped, the first failure is returned once every output was attempted
+fn batch<P: SamplePixel>(settings: &Settings, count: usize) -> Result<(), Error> {
+    let args = &settings.args;
+    let template = args
+        .output
+        .as_ref()
+        .map_or(DEFAULT_OUTPUT_TEMPLATE.into(), |output| {
+            output.to_string_lossy().into_owned()
+        });
+    if count > 1 && !template.contains("{index}") && !template.contains("{seed}") {
+        Args::command()
+            .error(
+                ErrorKind::ValueValidation,
+                "--output must contain {index} or {seed} when generating more than one output",
+            )
+            .exit();
+    }
+    let seed = args.seed.unwrap_or_else(rand::random);
+    println!("seed: {seed}");
+    let ruleset = settings.first_generator::<P>(seed)?.ruleset().clone();
+    let start = Instant::now();
+    let next_index = AtomicUsize::new(0);
+    let total_attempts = AtomicUsize::new(0);
+    let failures = AtomicUsize::new(0);
+    let first_error = Mutex::new(None);
+    // parallel outputs split the threads between them so that their parallel attempts do not add up to more threads
+    let (workers, attempt_threads) = if args.parallel {
+        let threads = args
+            .threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get));
+        let workers = threads.clamp(1, count.max(1));
+        (workers, Some((threads / workers).max(1)))
+    } else {
+        (1, args.threads)
+    };
+    let fail_output = |index: usize, error: Error| {
+        eprintln!("output {index}: {error}");
+        failures.fetch_add(1, Ordering::Relaxed);
+        first_error.lock().unwrap().get_or_insert(error);
+    };
+    let generate = || loop {
+        let index = next_index.fetch_add(1, Ordering::Relaxed);
+        if index >= count {
+            return;
+        }
+        let output_start = Instant::now();
+        // each output reserves a block of derived seeds so that its attempts never reuse another output's seed
+        let output_seed = parallel::derive_seed(seed, index * args.attempts.max(1));
+        let (generator, seed, attempts) =
+            match settings.solve(&ruleset, output_seed, attempt_threads) {
+                Ok(solution) => solution,
+                Err(error) => {
+                    if let Error::AttemptLimit(attempts) = error {
+                        total_attempts.fetch_add(attempts, Ordering::Relaxed);
+                    }
+                    fail_output(index, error);
+                    continue;
+                }
+            };
+        total_attempts.fetch_add(attempts, Ordering::Relaxed);
+        let path = PathBuf::from(
+            template
+                .replace("{index}", &index.to_string())
+                .replace("{seed}", &seed.to_string()),
+        );
+        if let Err(error) = write_output(&generator, &path, args.render, args.scale) {
+            fail_output(index, error);
+            continue;
+        }
+        println!(
+            "{}: seed {seed}, attempts: {attempts}, {:.2?}",
+            path.display(),
+            output_start.elapsed()
+        );
+    };
+    if workers > 1 {
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(generate);
+            }
+        });
+    } else {
+        generate();
+    }
+    let elapsed = start.elapsed();
+    let failures = failures.into_inner();
+    println!(
+        "{} of {count} outputs in {elapsed:.2?}, {:.2?} per output, attempts: {}",
+        count - failures,
+        elapsed / count.max(1) as u32,
+        total_attempts.into_inner()
+    );
+    match first_error.into_inner().unwrap() {
+        Some(error) => Err(error),
+        None => Ok(()),
     }
 }
 
-fn update<T: Rng>(model: &mut Model<T>) {
+/// Renders a collapsed generator to the path, warning if a wrapping output does not tile seamlessly
+fn write_output<P: SamplePixel>(
+    generator: &Generator<P>,
+    path: &Path,
+    render: Render,
+    scale: u32,
+) -> Result<(), Error> {
+    let image_buffer = generator
+        .render(render, scale)
+        .expect("Finished collapse must mean all cells have one option");
+    if generator.grid().wrapping() != Wrapping::None && !generator.is_seamless() {
+        eprintln!("warning: output does not tile seamlessly");
+    }
+    P::save(&image_buffer, path).map_err(|error| Error::UnwritableOutput {
+        path: path.to_path_buf(),
+        source: error.into(),
+    })
+}
+
+fn update<P: SamplePixel>(model: &mut Model<P>) -> Result<(), Error> {
     if model.collapsing {
-        let result = model.grid.collapse(&model.tiles, &mut model.rng);
+        let result = model.generator.step();
         match result {
             Ok(true) => (),
             Ok(false) => {
                 if model.repeating {
-                    model.grid.regenerate(false)
+                    model.generator.regenerate(false)
                 } else {
                     model.collapsing = false
                 }
             }
-            Err(Exhausted) => {
-                model.grid.regenerate(true);
-            }
+            Err(Error::Contradiction) => model.generator.retry()?,
+            Err(error) => return Err(error),
         }
         if !model.collapsing && result.is_ok() {
             if let Some(output) = &model.output {
-                let grid = &model.grid;
-                let image_buffer =
-                    ImageBuffer::from_fn(grid.width() as u32, grid.height() as u32, |x, y| {
-                        grid.get((x as usize, y as usize))
-                            .map(|cell| {
-                                let tile_index = cell.options.iter().next().expect(
-                                    "Finished collapse must mean all cells have one option",
-                                );
-                                let image = &model.tiles[*tile_index].image;
-                                *image.get_pixel(image.width() / 2, image.height() / 2)
-                            })
-                            .expect("All tiles will have a center pixel")
-                    });
-                let _ = image::save_buffer(
-                    output,
-                    image_buffer.as_bytes(),
-                    image_buffer.width(),
-                    image_buffer.height(),
-                    ExtendedColorType::Rgb8,
-                );
+                write_output(&model.generator, output, model.render, model.scale)?;
             }
         }
     }
+    Ok(())
 }
 
-fn view<T>(app: &App, model: &Model<T>, frame: Frame) {
+fn view<P: TilePixel>(app: &App, model: &Model<P>, frame: Frame) {
     let draw = app.draw();
     draw.background().color(PLUM);
-    let grid = &model.grid;
-    let tiles = &model.tiles;
+    let grid = model.generator.grid();
+    let tiles = model.generator.tiles();
     let win = app.window_rect();
     let (frame_width, frame_height) = win.w_h();
     let tile_width = frame_width / (grid.width() as u32) as f32;
     let draw = draw
         .translate(Vec3::new(-frame_width / 2., frame_height / 2., 0.))
         .scale_y(-1.);
-    for (x, y, cell) in grid.cells() {
-        cell.draw(&draw, tiles, x as u32, y as u32, tile_width);
+    for (i, (x, y, cell)) in grid.cells().enumerate() {
+        if !grid.exists(i) {
+            continue;
+        }
+        draw_cell(cell, &draw, tiles, x as u32, y as u32, tile_width);
     }
     draw.to_frame(app, &frame).unwrap();
 }
+
+fn draw_cell<P: TilePixel>(
+    cell: &Cell,
+    draw: &Draw,
+    tiles: &[Tile<P>],
+    x: u32,
+    y: u32,
+    width: f32,
+) {
+    if cell.options().is_empty() {
+        draw.rect()
+            .x_y((x as f32 + 0.5) * width, (y as f32 + 0.5) * width)
+            .w_h(width, width)
+            .color(Rgb::from_components((255u8, 0u8, 100u8)))
+            .stroke_weight((width * 0.03).max(2.));
+    } else {
+        let mut sum_r = 0;
+        let mut sum_g = 0;
+        let mut sum_b = 0;
+        let mut count = 0;
+        for tile_index in cell.options().iter() {
+            let tile = &tiles[tile_index];
+            let frequency = tile.frequency;
+            let image::Rgba([r, g, b, _]) = tile.center_pixel().to_rgba8();
+            sum_r += r as u32 * frequency;
+            sum_g += g as u32 * frequency;
+            sum_b += b as u32 * frequency;
+            count += frequency;
+        }
+        sum_r /= count;
+        sum_g /= count;
+        sum_b /= count;
+        draw.rect()
+            .x_y((x as f32 + 0.5) * width, (y as f32 + 0.5) * width)
+            .w_h(width, width)
+            .color(Rgb::from_components((
+                sum_r as u8,
+                sum_g as u8,
+                sum_b as u8,
+            )));
+        // .stroke_weight((width * 0.03).max(2.));
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{error::Error, image_impls::TilePixel, Generator};
+
+/// The attempt of [`solve_parallel`] that collapsed first
+pub struct Solution<P: TilePixel> {
+    /// The fully collapsed generator
+    pub generator: Generator<P>,
+    /// Index of the attempt, starting from 0
+    pub attempt: usize,
+    /// The seed the attempt was built with, building the same generator with this seed replays it
+    pub seed: u64,
+}
+
+/// The seed of the `index`th attempt or output derived from `seed`, index 0 uses `seed` itself so that it matches
+/// a sequential run
+pub fn derive_seed(seed: u64, index: usize) -> u64 {
+    if index == 0 {
+        return seed;
+    }
+    // splitmix64, mixed so that seeds derived from derived seeds do not line up with each other
+    let mut z = seed.wrapping_add((index as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Runs up to `attempts` independent attempts spread over `threads` threads, or one thread per available core if
+/// `None`. Each attempt builds its generator with `build` from a seed derived with [`derive_seed`] and gives up
+/// on its first contradiction, and the remaining attempts are cancelled as soon as one collapses.
+/// Fails with [`Error::AttemptLimit`] if every attempt failed, or with the first error other than a contradiction
+pub fn solve_parallel<P, F>(
+    build: F,
+    seed: u64,
+    attempts: usize,
+    threads: Option<usize>,
+) -> Result<Solution<P>, Error>
+where
+    P: TilePixel,
+    F: Fn(u64) -> Result<Generator<P>, Error> + Sync,
+{
+    let threads = threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+        .clamp(1, attempts.max(1));
+    let next_attempt = AtomicUsize::new(0);
+    let finished = AtomicBool::new(false);
+    let result = Mutex::new(None);
+    // the first attempt to finish, by collapsing or by an error that every attempt would run into, decides the result
+    let finish = |outcome| {
+        if !finished.swap(true, Ordering::Relaxed) {
+            *result.lock().unwrap() = Some(outcome);
+        }
+    };
+    let run = || loop {
+        let attempt = next_attempt.fetch_add(1, Ordering::Relaxed);
+        if attempt >= attempts || finished.load(Ordering::Relaxed) {
+            return;
+        }
+        let seed = derive_seed(seed, attempt);
+        let mut generator = match build(seed) {
+            Ok(generator) => generator,
+            Err(error) => return finish(Err(error)),
+        };
+        loop {
+            if finished.load(Ordering::Relaxed) {
+                return;
+            }
+            match generator.step() {
+                Ok(true) => (),
+                Ok(false) => {
+                    return finish(Ok(Solution {
+                        generator,
+                        attempt,
+                        seed,
+                    }))
+                }
+                Err(Error::Contradiction) => break,
+                Err(error) => return finish(Err(error)),
+            }
+        }
+    };
+    // the calling thread counts as one of the threads
+    thread::scope(|scope| {
+        for _ in 1..threads {
+            scope.spawn(run);
+        }
+        run();
+    });
+    result
+        .into_inner()
+        .unwrap()
+        .unwrap_or(Err(Error::AttemptLimit(attempts)))
+}
@@ -0,0 +1,179 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use image::Primitive;
+use num_traits::ToPrimitive;
+
+use crate::{image_impls::TilePixel, tile::TileImage};
+
+/// How the colors of a noisy sample are reduced before tiles are extracted
+#[derive(Clone, Copy, Debug)]
+pub enum Quantization {
+    /// K-means clustering down to at most this many colors, 0 is treated as 1
+    Palette(usize),
+    /// Merges colors whose channels all differ by at most this much, on a 0 to 255 scale,
+    /// into the more frequent color
+    Tolerance(f64),
+}
+
+/// The effect of quantizing a sample, patterns are counted as unique tiles even when wildcards merge them further
+#[derive(Clone, Copy, Debug)]
+pub struct QuantizeReport {
+    pub colors_before: usize,
+    pub colors_after: usize,
+    pub patterns_before: usize,
+    pub patterns_after: usize,
+}
+
+impl QuantizeReport {
+    /// How many fewer unique patterns the quantized sample has
+    pub fn patterns_saved(&self) -> usize {
+        self.patterns_before.saturating_sub(self.patterns_after)
+    }
+}
+
+const K_MEANS_ITERATIONS: usize = 20;
+
+struct Color<P> {
+    pixel: P,
+    /// Channels scaled to 0 to 255
+    channels: Vec<f64>,
+    count: usize,
+}
+
+/// Replaces every pixel of the image by its color in the reduced palette, returning the image and the number of colors before and after.
+/// Colors are only ever replaced by other colors of the sample
+pub fn quantize<P: TilePixel>(
+    image: &TileImage<P>,
+    quantization: Quantization,
+) -> (TileImage<P>, usize, usize) {
+    let max = P::Subpixel::DEFAULT_MAX_VALUE.to_f64().unwrap_or(1.);
+    let mut colors: Vec<Color<P>> = Vec::new();
+    let mut color_indexes: HashMap<Vec<u64>, usize> = HashMap::new();
+    let mut pixel_colors = Vec::with_capacity(image.len());
+    for pixel in image.pixels() {
+        let channels: Vec<f64> = pixel
+            .channels()
+            .iter()
+            .map(|channel| channel.to_f64().unwrap_or(0.) / max * 255.)
+            .collect();
+        let key = channels.iter().map(|channel| channel.to_bits()).collect();
+        let color_index = *color_indexes.entry(key).or_insert_with(|| {
+            colors.push(Color {
+                pixel: *pixel,
+                channels,
+                count: 0,
+            });
+            colors.len() - 1
+        });
+        colors[color_index].count += 1;
+        pixel_colors.push(color_index);
+    }
+    let replacements = match quantization {
+        Quantization::Palette(max_colors) => k_means(&colors, max_colors.max(1)),
+        Quantization::Tolerance(tolerance) => merge_within(&colors, tolerance),
+    };
+    let mut colors_after = replacements.clone();
+    colors_after.sort_unstable();
+    colors_after.dedup();
+    let mut quantized = image.clone();
+    for (pixel, color_index) in quantized.pixels_mut().zip(pixel_colors) {
+        *pixel = colors[replacements[color_index]].pixel;
+    }
+    (quantized, colors.len(), colors_after.len())
+}
+
+/// Clusters the colors with k-means, starting from colors spread as far apart as possible,
+/// and replaces every color by the most frequent color of its cluster
+fn k_means<P>(colors: &[Color<P>], max_colors: usize) -> Vec<usize> {
+    let distance =
+        |a: &[f64], b: &[f64]| -> f64 { a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum() };
+    let Some(most_frequent) =
+        (0..colors.len()).max_by_key(|&color_index| colors[color_index].count)
+    else {
+        return Vec::new();
+    };
+    let mut centres = vec![colors[most_frequent].channels.clone()];
+    while centres.len() < max_colors.min(colors.len()) {
+        let farthest = colors
+            .iter()
+            .map(|color| {
+                centres
+                    .iter()
+                    .map(|centre| distance(&color.channels, centre))
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .filter(|&(_, distance)| distance > 0.);
+        let Some((color_index, _)) = farthest else {
+            break;
+        };
+        centres.push(colors[color_index].channels.clone());
+    }
+    let mut clusters = vec![0; colors.len()];
+    for _ in 0..K_MEANS_ITERATIONS {
+        let mut changed = false;
+        for (color, cluster) in colors.iter().zip(&mut clusters) {
+            let nearest = (0..centres.len())
+                .min_by(|&a, &b| {
+                    distance(&color.channels, &centres[a])
+                        .total_cmp(&distance(&color.channels, &centres[b]))
+                })
+                .unwrap_or(0);
+            changed |= *cluster != nearest;
+            *cluster = nearest;
+        }
+        let mut sums = vec![(vec![0.; centres[0].len()], 0.); centres.len()];
+        for (color, &cluster) in colors.iter().zip(&clusters) {
+            let (sum, weight) = &mut sums[cluster];
+            for (sum, channel) in sum.iter_mut().zip(&color.channels) {
+                *sum += channel * color.count as f64;
+            }
+            *weight += color.count as f64;
+        }
+        for (centre, (sum, weight)) in centres.iter_mut().zip(sums) {
+            if weight > 0. {
+                *centre = sum.into_iter().map(|sum| sum / weight).collect();
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    let mut representatives: Vec<Option<usize>> = vec![None; centres.len()];
+    for (color_index, &cluster) in clusters.iter().enumerate() {
+        let representative = &mut representatives[cluster];
+        if representative.is_none_or(|kept| colors[kept].count < colors[color_index].count) {
+            *representative = Some(color_index);
+        }
+    }
+    clusters
+        .into_iter()
+        .map(|cluster| {
+            representatives[cluster].expect("Every cluster of a color has a representative")
+        })
+        .collect()
+}
+
+/// Replaces every color by the most frequent color within the tolerance of it
+fn merge_within<P>(colors: &[Color<P>], tolerance: f64) -> Vec<usize> {
+    let mut by_count: Vec<usize> = (0..colors.len()).collect();
+    by_count.sort_by_key(|&color_index| Reverse(colors[color_index].count));
+    let mut palette: Vec<usize> = Vec::new();
+    let mut replacements = vec![0; colors.len()];
+    for color_index in by_count {
+        let channels = &colors[color_index].channels;
+        let kept = palette.iter().copied().find(|&kept_index| {
+            colors[kept_index]
+                .channels
+                .iter()
+                .zip(channels)
+                .all(|(a, b)| (a - b).abs() <= tolerance)
+        });
+        replacements[color_index] = kept.unwrap_or_else(|| {
+            palette.push(color_index);
+            color_index
+        });
+    }
+    replacements
+}
@@ -0,0 +1,73 @@
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+
+use crate::{
+    grid::Grid,
+    image_impls::TilePixel,
+    tile::{Tile, TileImage},
+};
+
+/// How a collapsed grid is turned into an image
+#[derive(Copy, Clone, ValueEnum, Default)]
+pub enum Render {
+    /// One pixel per cell, the center pixel of its tile
+    #[default]
+    Center,
+    /// Each cell's whole tile as an NxN block, for an output N times the size of the grid
+    Pattern,
+    /// Each cell's tile placed at the cell's position so that neighboring tiles overlap,
+    /// for an output of (W+N-1)x(H+N-1)
+    Overlap,
+}
+
+/// Renders the collapsed grid, upscaled by an integer `scale` with nearest-neighbor filtering, a scale of 0 is treated as 1.
+/// Pixels keep the sample's alpha and bit depth, and pixels not covered by a cell inside the mask are left transparent.
+/// `None` if the grid has not fully collapsed
+pub fn render<P: TilePixel>(
+    grid: &Grid,
+    tiles: &[Tile<P>],
+    render: Render,
+    scale: u32,
+) -> Option<TileImage<P::Output>> {
+    let tile_size = tiles.first().map_or(1, |tile| tile.image.width());
+    let (width, height) = (grid.width() as u32, grid.height() as u32);
+    let mut image = match render {
+        Render::Center => TileImage::new(width, height),
+        Render::Pattern => TileImage::new(width * tile_size, height * tile_size),
+        Render::Overlap => TileImage::new(width + tile_size - 1, height + tile_size - 1),
+    };
+    for (i, (x, y, cell)) in grid.cells().enumerate() {
+        let (x, y) = (x as u32, y as u32);
+        if !grid.exists(i) {
+            if let Render::Center = render {
+                image.put_pixel(x, y, P::transparent());
+            }
+            continue;
+        }
+        let mut options = cell.options().iter();
+        let (Some(tile_index), None) = (options.next(), options.next()) else {
+            return None;
+        };
+        let tile = &tiles[tile_index];
+        match render {
+            Render::Center => image.put_pixel(x, y, tile.center_pixel().to_output()),
+            Render::Pattern => draw_tile(&mut image, tile, x * tile_size, y * tile_size),
+            Render::Overlap => draw_tile(&mut image, tile, x, y),
+        }
+    }
+    if scale > 1 {
+        image = imageops::resize(
+            &image,
+            image.width() * scale,
+            image.height() * scale,
+            FilterType::Nearest,
+        );
+    }
+    Some(image)
+}
+
+fn draw_tile<P: TilePixel>(image: &mut TileImage<P::Output>, tile: &Tile<P>, x: u32, y: u32) {
+    for (dx, dy, pixel) in tile.image.enumerate_pixels() {
+        image.put_pixel(x + dx, y + dy, pixel.to_output());
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::{self, Read, Write};
+
+use image::{Pixel, Primitive, Rgba};
+use num_traits::{NumCast, ToPrimitive};
+
+use crate::{
+    domain::Domain,
+    error::Error,
+    grid::Grid,
+    image_impls::{BorderStyle, Symmetry, TilePixel},
+    tile::{self, Direction, Directions, Tile, TileImage},
+};
+
+const MAGIC: &[u8; 4] = b"WFCR";
+/// Bumped whenever the file layout changes, files of other versions are rejected
+pub const RULESET_VERSION: u32 = 1;
+
+/// The patterns of a sample with their weights and which patterns may neighbor each other,
+/// built once and shared by every generation, including generations on other threads
+pub struct Ruleset<P: Pixel = Rgba<u8>> {
+    tiles: Vec<Tile<P>>,
+    tile_size: u32,
+    border_style: BorderStyle,
+}
+
+// rulesets are shared between the threads solving separate grids
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Ruleset>();
+    assert_send_sync::<Ruleset<Rgba<u16>>>();
+    assert_send_sync::<Grid>();
+};
+
+/// The start of a ruleset file, enough to tell which pixel type it was saved with
+#[derive(Clone, Copy)]
+pub struct RulesetHeader {
+    pub version: u32,
+    pub channel_count: u8,
+    /// The maximum value of a channel, 255 for 8-bit pixels
+    pub max_value: f64,
+    pub tile_size: u32,
+    pub border_style: BorderStyle,
+    pub tile_count: u32,
+}
+
+impl<P: TilePixel> Ruleset<P> {
+    /// Extracts the patterns of the sample, see [`tile::extract_tiles`].
+    /// Fails with [`Error::InvalidTileSize`] unless the tile size is odd and fits the sample
+    pub fn from_sample(
+        sample: &TileImage<P>,
+        tile_size: u32,
+        border_style: BorderStyle,
+        symmetry: Symmetry,
+        wildcards: bool,
+    ) -> Result<Ruleset<P>, Error> {
+        check_tile_size(sample, tile_size)?;
+        let tiles = tile::extract_tiles(sample, tile_size, border_style, symmetry, wildcards);
+        Ok(Ruleset::from_tiles(tiles, tile_size, border_style))
+    }
+
+    /// Drops the merged duplicates, keeping the order of the remaining tiles
+    pub fn from_tiles(
+        tiles: Vec<Tile<P>>,
+        tile_size: u32,
+        border_style: BorderStyle,
+    ) -> Ruleset<P> {
+        let mut new_indexes = vec![None; tiles.len()];
+        let mut kept_count = 0;
+        for (tile_index, tile) in tiles.iter().enumerate() {
+            if tile.frequency != 0 {
+                new_indexes[tile_index] = Some(kept_count);
+                kept_count += 1;
+            }
+        }
+        let remap = |domain: &Domain| {
+            Domain::from_indexes(
+                kept_count,
+                domain
+                    .iter()
+                    .filter_map(|tile_index| new_indexes[tile_index]),
+            )
+        };
+        let tiles = tiles
+            .into_iter()
+            .filter(|tile| tile.frequency != 0)
+            .map(|tile| {
+                let neighbors = &tile.neighbors;
+                Tile {
+                    neighbors: Directions {
+                        up: remap(&neighbors.up),
+                        down: remap(&neighbors.down),
+                        left: remap(&neighbors.left),
+                        right: remap(&neighbors.right),
+                    },
+                    ..tile
+                }
+            })
+            .collect();
+        Ruleset {
+            tiles,
+            tile_size,
+            border_style,
+        }
+    }
+
+    pub fn tiles(&self) -> &[Tile<P>] {
+        &self.tiles
+    }
+
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    pub fn border_style(&self) -> BorderStyle {
+        self.border_style
+    }
+
+    /// Writes the ruleset in a versioned binary format, channels are stored as `f64` so that any pixel type round trips
+    pub fn save(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&RULESET_VERSION.to_le_bytes())?;
+        writer.write_all(&[P::CHANNEL_COUNT])?;
+        writer.write_all(&max_value::<P>().to_le_bytes())?;
+        writer.write_all(&self.tile_size.to_le_bytes())?;
+        writer.write_all(&[self.border_style as u8])?;
+        writer.write_all(&(self.tiles.len() as u32).to_le_bytes())?;
+        for tile in &self.tiles {
+            writer.write_all(&tile.frequency.to_le_bytes())?;
+            let edges = Direction::ALL
+                .into_iter()
+                .enumerate()
+                .fold(0u8, |flags, (bit, direction)| {
+                    flags | (tile.edges[direction] as u8) << bit
+                });
+            writer.write_all(&[edges])?;
+            for pixel in tile.image.pixels() {
+                for channel in pixel.channels() {
+                    writer.write_all(&channel.to_f64().unwrap_or(0.).to_le_bytes())?;
+                }
+            }
+            let neighbors = &tile.neighbors;
+            for direction in Direction::ALL {
+                writer.write_all(&(neighbors[direction].len() as u32).to_le_bytes())?;
+                for tile_index in neighbors[direction].iter() {
+                    writer.write_all(&(tile_index as u32).to_le_bytes())?;
+                }
+            }
+        }
+        writer.flush()
+    }
+
+    /// Reads a ruleset written by [`Ruleset::save`] with the same pixel type
+    pub fn load(mut reader: impl Read) -> io::Result<Ruleset<P>> {
+        let header = RulesetHeader::read(&mut reader)?;
+        if header.channel_count != P::CHANNEL_COUNT || header.max_value != max_value::<P>() {
+            return Err(invalid_data(format!(
+                "ruleset was saved with {} channels of up to {}, expected {} channels of up to {}",
+                header.channel_count,
+                header.max_value,
+                P::CHANNEL_COUNT,
+                max_value::<P>()
+            )));
+        }
+        let tile_count = header.tile_count as usize;
+        let tile_size = header.tile_size;
+        let subpixel_count = (tile_size as u64).pow(2) * P::CHANNEL_COUNT as u64;
+        // the header cannot be trusted, so nothing is allocated until the data it describes has been read
+        let mut tiles = Vec::new();
+        let mut neighbor_indices = Vec::new();
+        for _ in 0..tile_count {
+            let frequency = read_u32(&mut reader)?;
+            let edge_flags = read_array::<1>(&mut reader)?[0];
+            let mut edges = Directions::default();
+            for (bit, direction) in Direction::ALL.into_iter().enumerate() {
+                edges[direction] = edge_flags & (1 << bit) != 0;
+            }
+            let mut subpixels = Vec::new();
+            for _ in 0..subpixel_count {
+                let value = f64::from_le_bytes(read_array(&mut reader)?);
+                subpixels.push(NumCast::from(value).ok_or_else(|| {
+                    invalid_data(format!("channel value {value} is out of range"))
+                })?);
+            }
+            let image = TileImage::<P>::from_raw(tile_size, tile_size, subpixels)
+                .ok_or_else(|| invalid_data(format!("tile size {tile_size} is too large")))?;
+            let mut indices = Directions::<Vec<usize>>::default();
+            for direction in Direction::ALL {
+                for _ in 0..read_u32(&mut reader)? {
+                    let tile_index = read_u32(&mut reader)? as usize;
+                    if tile_index >= tile_count {
+                        return Err(invalid_data(format!(
+                            "neighbor {tile_index} is out of range"
+                        )));
+                    }
+                    indices[direction].push(tile_index);
+                }
+            }
+            neighbor_indices.push(indices);
+            tiles.push(Tile {
+                image,
+                neighbors: Directions::default(),
+                frequency,
+                border_style: header.border_style,
+                edges,
+            });
+        }
+        for (tile, indices) in tiles.iter_mut().zip(neighbor_indices) {
+            for direction in Direction::ALL {
+                tile.neighbors[direction] =
+                    Domain::from_indexes(tile_count, indices[direction].iter().copied());
+            }
+        }
+        if tiles.iter().all(|tile| tile.frequency == 0) {
+            return Err(invalid_data("ruleset has no tiles to place".to_string()));
+        }
+        // propagation relies on every tile listing the tiles that list it in the opposing direction
+        for (tile_index, tile) in tiles.iter().enumerate() {
+            for direction in Direction::ALL {
+                for neighbor in tile.neighbors[direction].iter() {
+                    if !tiles[neighbor].neighbors[direction.opposing()].contains(tile_index) {
+                        return Err(invalid_data(format!(
+                            "tile {tile_index} may have tile {neighbor} to the {direction:?}, \
+                             but not the other way around"
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(Ruleset {
+            tiles,
+            tile_size,
+            border_style: header.border_style,
+        })
+    }
+}
+
+impl RulesetHeader {
+    /// Reads only the header, leaving the reader at the first tile
+    pub fn read(mut reader: impl Read) -> io::Result<RulesetHeader> {
+        if &read_array::<4>(&mut reader)? != MAGIC {
+            return Err(invalid_data("not a ruleset file".to_string()));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != RULESET_VERSION {
+            return Err(invalid_data(format!(
+                "ruleset version {version} is not supported, expected version {RULESET_VERSION}"
+            )));
+        }
+        let channel_count = read_array::<1>(&mut reader)?[0];
+        let max_value = f64::from_le_bytes(read_array(&mut reader)?);
+        let tile_size = read_u32(&mut reader)?;
+        if tile_size % 2 == 0 {
+            return Err(invalid_data(format!(
+                "tile size must be odd, got {tile_size}"
+            )));
+        }
+        let border_style = match read_array::<1>(&mut reader)?[0] {
+            0 => BorderStyle::Looping,
+            1 => BorderStyle::Clamped,
+            style => return Err(invalid_data(format!("unknown border style {style}"))),
+        };
+        let tile_count = read_u32(&mut reader)?;
+        Ok(RulesetHeader {
+            version,
+            channel_count,
+            max_value,
+            tile_size,
+            border_style,
+            tile_count,
+        })
+    }
+}
+
+/// Tiles need an odd size so that they have a center pixel, and must fit inside the sample they are extracted from
+pub(crate) fn check_tile_size<P: Pixel>(
+    sample: &TileImage<P>,
+    tile_size: u32,
+) -> Result<(), Error> {
+    if tile_size % 2 != 1 || tile_size > sample.width() || tile_size > sample.height() {
+        return Err(Error::InvalidTileSize(tile_size));
+    }
+    Ok(())
+}
+
+fn max_value<P: Pixel>() -> f64 {
+    P::Subpixel::DEFAULT_MAX_VALUE.to_f64().unwrap_or(1.)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    read_array(reader).map(u32::from_le_bytes)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -1,14 +1,21 @@
-use crate::image_impls::{BorderStyle, LoopingSubImage, LoopingView};
+use crate::{
+    domain::Domain,
+    image_impls::{BorderStyle, LoopingSubImage, LoopingView, Symmetry, Tilable, TilePixel},
+};
 
 use std::{
-    cell::RefCell,
-    collections::HashSet,
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
     ops::{Index, IndexMut},
 };
 
-use image::RgbImage;
+use image::{GenericImageView, ImageBuffer, Pixel, Rgba};
+use num_traits::ToPrimitive;
+
+/// An image made of pixels of type `P`
+pub type TileImage<P> = ImageBuffer<P, Vec<<P as Pixel>::Subpixel>>;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Directions<T> {
     pub up: T,
     pub down: T,
@@ -24,62 +31,306 @@ pub enum Direction {
     Right,
 }
 
-pub struct Tile {
-    pub image: RgbImage,
-    pub neighbors: RefCell<Directions<HashSet<usize>>>,
+pub struct Tile<P: Pixel = Rgba<u8>> {
+    pub image: TileImage<P>,
+    /// The tiles that may be placed next to this tile in each direction, fixed once the tiles are extracted
+    pub neighbors: Directions<Domain>,
     pub frequency: u32,
     pub border_style: BorderStyle,
+    /// Which edges of the sample this tile was centered on, only set for the tile's original orientation
+    pub edges: Directions<bool>,
+}
+
+/// Extracts every tile of the sample, merging duplicates and finding which tiles can neighbor each other.
+///
+/// Duplicate tiles are left in place with a frequency of 0. With `wildcards`, fully transparent pixels match any pixel,
+/// and tiles are merged into the matching tile with the fewest transparent pixels.
+/// Without wildcards, tiles and their overlaps are hashed so that this takes close to linear time
+pub fn extract_tiles<P: TilePixel>(
+    image: &TileImage<P>,
+    tile_size: u32,
+    border_style: BorderStyle,
+    symmetry: Symmetry,
+    wildcards: bool,
+) -> Vec<Tile<P>> {
+    let tiles = sample_tiles(image, tile_size, border_style, symmetry);
+    if wildcards {
+        link_tiles_naive(tiles, wildcards)
+    } else {
+        link_tiles_hashed(tiles)
+    }
+}
+
+/// [`extract_tiles`] comparing every pair of tiles, kept as the reference for the hashed version
+pub fn extract_tiles_naive<P: TilePixel>(
+    image: &TileImage<P>,
+    tile_size: u32,
+    border_style: BorderStyle,
+    symmetry: Symmetry,
+    wildcards: bool,
+) -> Vec<Tile<P>> {
+    let tiles = sample_tiles(image, tile_size, border_style, symmetry);
+    link_tiles_naive(tiles, wildcards)
 }
 
-impl Tile {
-    pub fn view_in_direction(&self, direction: Direction) -> LoopingSubImage<&RgbImage> {
-        match direction {
-            Direction::Up => self.up_view(),
-            Direction::Down => self.down_view(),
-            Direction::Left => self.left_view(),
-            Direction::Right => self.right_view(),
+/// The number of unique tiles [`extract_tiles`] would keep without wildcards, found by hashing the tiles
+/// instead of linking them
+pub fn count_patterns<P: TilePixel>(
+    image: &TileImage<P>,
+    tile_size: u32,
+    border_style: BorderStyle,
+    symmetry: Symmetry,
+) -> usize {
+    let mut seen: HashMap<u64, Vec<TileImage<P>>> = HashMap::new();
+    sample_tiles(image, tile_size, border_style, symmetry)
+        .into_iter()
+        .filter(|tile| {
+            let bucket = seen.entry(hash_pixels(&tile.image)).or_default();
+            if bucket.contains(&tile.image) {
+                return false;
+            }
+            bucket.push(tile.image.clone());
+            true
+        })
+        .count()
+}
+
+/// Every tile of the sample and its symmetry variants, before merging
+fn sample_tiles<P: TilePixel>(
+    image: &TileImage<P>,
+    tile_size: u32,
+    border_style: BorderStyle,
+    symmetry: Symmetry,
+) -> Vec<Tile<P>> {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    image
+        .tiles(tile_size, border_style)
+        .flat_map(|tile_view| {
+            let (xoffset, yoffset) = tile_view.offsets();
+            let (x, y) = (
+                xoffset + tile_size as i64 / 2,
+                yoffset + tile_size as i64 / 2,
+            );
+            let edges = Directions {
+                up: y == 0,
+                down: y == height - 1,
+                left: x == 0,
+                right: x == width - 1,
+            };
+            symmetry
+                .variants(tile_view.to_image())
+                .into_iter()
+                .enumerate()
+                .map(move |(variant, image)| Tile {
+                    image,
+                    neighbors: Directions::splat(Domain::empty(0)),
+                    frequency: 0,
+                    border_style,
+                    edges: if variant == 0 {
+                        edges.clone()
+                    } else {
+                        Directions::default()
+                    },
+                })
+        })
+        .collect()
+}
+
+fn link_tiles_naive<P: TilePixel>(mut tiles: Vec<Tile<P>>, wildcards: bool) -> Vec<Tile<P>> {
+    let mut neighbors = vec![Directions::splat(Domain::empty(tiles.len())); tiles.len()];
+
+    // tiles with fewer transparent pixels are kept first, so that wildcard tiles merge into fully specified ones
+    let mut order: Vec<usize> = (0..tiles.len()).collect();
+    if wildcards {
+        order.sort_by_key(|&tile_index| {
+            tiles[tile_index]
+                .image
+                .pixels()
+                .filter(|pixel| pixel.is_transparent())
+                .count()
+        });
+    }
+    let mut originals = vec![0; tiles.len()];
+    let mut kept: Vec<usize> = Vec::new();
+    for tile_index in order {
+        let tile = &tiles[tile_index];
+        originals[tile_index] = kept
+            .iter()
+            .copied()
+            .find(|&kept_index| tiles[kept_index].compatible(tile, 0, 0, wildcards))
+            .unwrap_or(tile_index);
+        if originals[tile_index] == tile_index {
+            kept.push(tile_index);
         }
     }
 
-    pub fn up_view(&self) -> LoopingSubImage<&RgbImage> {
-        self.image.looping_view(
-            0,
-            0,
-            self.image.width(),
-            self.image.height().div_ceil(2),
-            self.border_style,
-        )
+    for outer_index in 0..tiles.len() {
+        let original_index = originals[outer_index];
+        tiles[original_index].frequency += 1;
+        for dir in Direction::ALL {
+            tiles[original_index].edges[dir] |= tiles[outer_index].edges[dir];
+        }
+        if original_index != outer_index {
+            continue;
+        }
+        let original_tile = &tiles[original_index];
+        for dir in [Direction::Up, Direction::Right] {
+            let opp_dir = dir.opposing();
+            let (dx, dy) = dir.offset();
+            if original_tile.compatible(original_tile, dx, dy, wildcards) {
+                neighbors[original_index][dir].insert(outer_index);
+                neighbors[original_index][opp_dir].insert(outer_index);
+            }
+        }
+        for (inner_index, inner_tile) in tiles.iter().enumerate().skip(outer_index + 1) {
+            for dir in Direction::ALL {
+                let opp_dir = dir.opposing();
+                let (dx, dy) = dir.offset();
+                if original_tile.compatible(inner_tile, dx, dy, wildcards) {
+                    neighbors[original_index][dir].insert(inner_index);
+                    neighbors[inner_index][opp_dir].insert(original_index);
+                }
+            }
+        }
     }
-    pub fn down_view(&self) -> LoopingSubImage<&RgbImage> {
-        self.image.looping_view(
-            0,
-            self.image.height() as i64 / 2,
-            self.image.width(),
-            self.image.height().div_ceil(2),
-            self.border_style,
-        )
+    freeze_neighbors(tiles, neighbors)
+}
+
+/// Merges tiles with equal hashes and only compares overlaps of tiles with equal hashes
+fn link_tiles_hashed<P: TilePixel>(mut tiles: Vec<Tile<P>>) -> Vec<Tile<P>> {
+    let mut neighbors = vec![Directions::splat(Domain::empty(tiles.len())); tiles.len()];
+
+    let mut kept: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut originals = Vec::with_capacity(tiles.len());
+    for tile_index in 0..tiles.len() {
+        let tile = &tiles[tile_index];
+        let bucket = kept.entry(hash_pixels(&tile.image)).or_default();
+        let original_index = bucket
+            .iter()
+            .copied()
+            .find(|&kept_index| tiles[kept_index].image == tile.image)
+            .unwrap_or_else(|| {
+                bucket.push(tile_index);
+                tile_index
+            });
+        originals.push(original_index);
+        tiles[original_index].frequency += 1;
+        for dir in Direction::ALL {
+            tiles[original_index].edges[dir] |= tiles[tile_index].edges[dir];
+        }
     }
-    pub fn left_view(&self) -> LoopingSubImage<&RgbImage> {
-        self.image.looping_view(
-            0,
-            0,
-            self.image.width().div_ceil(2),
-            self.image.height(),
-            self.border_style,
-        )
+    let originals: Vec<usize> = originals
+        .into_iter()
+        .enumerate()
+        .filter(|&(tile_index, original_index)| tile_index == original_index)
+        .map(|(tile_index, _)| tile_index)
+        .collect();
+
+    for dir in [Direction::Down, Direction::Right] {
+        let opp_dir = dir.opposing();
+        let (dx, dy) = dir.offset();
+        let mut by_overlap: HashMap<u64, Vec<usize>> = HashMap::new();
+        for &tile_index in &originals {
+            let overlap = tiles[tile_index].overlap_view(-dx, -dy);
+            by_overlap
+                .entry(hash_pixels(&overlap))
+                .or_default()
+                .push(tile_index);
+        }
+        for &tile_index in &originals {
+            let overlap = tiles[tile_index].overlap_view(dx, dy);
+            let Some(candidates) = by_overlap.get(&hash_pixels(&overlap)) else {
+                continue;
+            };
+            for &other_index in candidates {
+                if overlap == tiles[other_index].overlap_view(-dx, -dy) {
+                    neighbors[tile_index][dir].insert(other_index);
+                    neighbors[other_index][opp_dir].insert(tile_index);
+                }
+            }
+        }
     }
-    pub fn right_view(&self) -> LoopingSubImage<&RgbImage> {
+    freeze_neighbors(tiles, neighbors)
+}
+
+/// Hands each tile the neighbors found while linking, after which they no longer change
+fn freeze_neighbors<P: Pixel>(
+    mut tiles: Vec<Tile<P>>,
+    neighbors: Vec<Directions<Domain>>,
+) -> Vec<Tile<P>> {
+    for (tile, neighbors) in tiles.iter_mut().zip(neighbors) {
+        tile.neighbors = neighbors;
+    }
+    tiles
+}
+
+fn hash_pixels<I: GenericImageView>(image: &I) -> u64
+where
+    I::Pixel: TilePixel,
+{
+    let mut hasher = DefaultHasher::new();
+    image.dimensions().hash(&mut hasher);
+    for (.., pixel) in image.pixels() {
+        for channel in pixel.channels() {
+            // equal channels must hash equally, so negative zero is hashed as zero
+            let channel = channel.to_f64().unwrap_or(0.);
+            (channel + 0.).to_bits().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+impl<P: TilePixel> Tile<P> {
+    /// The pixel a cell collapsed to this tile is drawn with
+    pub fn center_pixel(&self) -> &P {
+        self.image
+            .get_pixel(self.image.width() / 2, self.image.height() / 2)
+    }
+
+    /// The region of this tile overlapped by a tile placed in the given direction
+    pub fn view_in_direction(&self, direction: Direction) -> LoopingSubImage<&TileImage<P>> {
+        let (dx, dy) = direction.offset();
+        self.overlap_view(dx, dy)
+    }
+
+    /// The region of this tile overlapped by a tile of the same size offset by `(dx, dy)`
+    pub fn overlap_view(&self, dx: i64, dy: i64) -> LoopingSubImage<&TileImage<P>> {
         self.image.looping_view(
-            self.image.width() as i64 / 2,
-            0,
-            self.image.width().div_ceil(2),
-            self.image.height(),
+            dx.max(0),
+            dy.max(0),
+            self.image.width() - dx.unsigned_abs() as u32,
+            self.image.height() - dy.unsigned_abs() as u32,
             self.border_style,
         )
     }
+
+    /// Whether `other` can be placed at `(dx, dy)` relative to this tile, that is the regions where they overlap agree.
+    /// With `wildcards`, fully transparent pixels agree with any pixel
+    pub fn compatible(&self, other: &Tile<P>, dx: i64, dy: i64, wildcards: bool) -> bool {
+        self.overlap_view(dx, dy)
+            .matches(&other.overlap_view(-dx, -dy), wildcards)
+    }
 }
 
 impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+
+    /// The offset of a neighboring cell in this direction
+    pub fn offset(&self) -> (i64, i64) {
+        use Direction::*;
+        match self {
+            Up => (0, -1),
+            Down => (0, 1),
+            Left => (-1, 0),
+            Right => (1, 0),
+        }
+    }
+
     pub fn opposing(&self) -> Direction {
         use Direction::*;
         match self {
@@ -91,6 +342,17 @@ impl Direction {
     }
 }
 
+impl<T: Clone> Directions<T> {
+    pub fn splat(value: T) -> Directions<T> {
+        Directions {
+            up: value.clone(),
+            down: value.clone(),
+            left: value.clone(),
+            right: value,
+        }
+    }
+}
+
 impl<T> Index<Direction> for Directions<T> {
     type Output = T;
 
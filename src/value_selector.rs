@@ -0,0 +1,207 @@
+use clap::ValueEnum;
+use image::Rgba;
+use rand::{distr::weighted::WeightedIndex, prelude::*, RngCore};
+
+use crate::{
+    domain::Domain,
+    grid::Grid,
+    image_impls::TilePixel,
+    tile::{Direction, Tile},
+};
+
+/// Chooses which of a selected cell's options it collapses to
+pub trait ValueSelector<P: TilePixel = Rgba<u8>> {
+    /// Picks one of the options of the cell at `grid_index`, only returning `None` if the cell has no options
+    fn choose(
+        &mut self,
+        grid: &Grid,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize>;
+}
+
+/// The built-in value selectors
+#[derive(Copy, Clone, ValueEnum, Default)]
+pub enum ValueSelection {
+    /// Sample options by how often they appear in the input
+    #[default]
+    Weighted,
+    /// Sample every option with the same probability
+    Uniform,
+    /// Sample options by their frequency raised to `1 / temperature`
+    Temperature,
+    /// The option that removes the fewest options from neighboring cells
+    LeastConstraining,
+    /// Always the most frequent option
+    MostFrequent,
+}
+
+impl ValueSelection {
+    /// `temperature` is only used by [`ValueSelection::Temperature`]
+    pub fn build<P: TilePixel>(&self, temperature: f64) -> Box<dyn ValueSelector<P> + Send> {
+        match self {
+            ValueSelection::Weighted => Box::new(Weighted),
+            ValueSelection::Uniform => Box::new(Uniform),
+            ValueSelection::Temperature => Box::new(Temperature(temperature)),
+            ValueSelection::LeastConstraining => Box::new(LeastConstraining),
+            ValueSelection::MostFrequent => Box::new(MostFrequent),
+        }
+    }
+}
+
+impl<P: TilePixel, V: ValueSelector<P> + ?Sized> ValueSelector<P> for Box<V> {
+    fn choose(
+        &mut self,
+        grid: &Grid,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        (**self).choose(grid, tiles, grid_index, rng)
+    }
+}
+
+/// Samples options weighted by their frequency in the input
+pub struct Weighted;
+
+/// Samples options uniformly
+pub struct Uniform;
+
+/// Samples options weighted by `frequency^(1 / temperature)`, so low temperatures favor common tiles
+/// and high temperatures approach uniform sampling. The temperature should be positive,
+/// otherwise only the most frequent options are sampled
+pub struct Temperature(pub f64);
+
+/// Picks the option that leaves neighboring cells the most options, breaking ties by frequency
+pub struct LeastConstraining;
+
+/// Picks the most frequent option, preferring the lowest tile index on ties
+pub struct MostFrequent;
+
+impl<P: TilePixel> ValueSelector<P> for Weighted {
+    fn choose(
+        &mut self,
+        grid: &Grid,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        let options = options(grid, grid_index);
+        sample_weighted(&options, rng, |tile_index| {
+            tiles[tile_index].frequency as f64
+        })
+    }
+}
+
+impl<P: TilePixel> ValueSelector<P> for Uniform {
+    fn choose(
+        &mut self,
+        grid: &Grid,
+        _: &[Tile<P>],
+        grid_index: usize,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        options(grid, grid_index).choose(rng).copied()
+    }
+}
+
+impl<P: TilePixel> ValueSelector<P> for Temperature {
+    fn choose(
+        &mut self,
+        grid: &Grid,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        let options = options(grid, grid_index);
+        let max_frequency = options
+            .iter()
+            .map(|&tile_index| tiles[tile_index].frequency)
+            .max()
+            .unwrap_or(0) as f64;
+        let temperature = self.0;
+        // relative to the most frequent option so that low temperatures cannot overflow
+        sample_weighted(&options, rng, |tile_index| {
+            let relative = tiles[tile_index].frequency as f64 / max_frequency;
+            if temperature > 0. {
+                (relative.ln() / temperature).exp()
+            } else if relative == 1. {
+                1.
+            } else {
+                0.
+            }
+        })
+    }
+}
+
+impl<P: TilePixel> ValueSelector<P> for LeastConstraining {
+    fn choose(
+        &mut self,
+        grid: &Grid,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        let neighbors: Vec<(Direction, &Domain)> = Direction::ALL
+            .into_iter()
+            .filter_map(|direction| {
+                grid.index_in_direction(grid_index, direction)
+                    .and_then(|index| grid.get_index(index))
+                    .map(|cell| (direction, cell.options()))
+            })
+            .collect();
+        let removed_options = |tile_index: usize| -> usize {
+            let tile_neighbors = &tiles[tile_index].neighbors;
+            neighbors
+                .iter()
+                .map(|&(direction, options)| {
+                    options.len() - options.intersection_len(&tile_neighbors[direction])
+                })
+                .sum()
+        };
+        let options = options(grid, grid_index);
+        let removed: Vec<usize> = options.iter().map(|&t| removed_options(t)).collect();
+        let least = *removed.iter().min()?;
+        let best: Vec<usize> = options
+            .into_iter()
+            .zip(removed)
+            .filter(|&(_, removed)| removed == least)
+            .map(|(tile_index, _)| tile_index)
+            .collect();
+        sample_weighted(&best, rng, |tile_index| tiles[tile_index].frequency as f64)
+    }
+}
+
+impl<P: TilePixel> ValueSelector<P> for MostFrequent {
+    fn choose(
+        &mut self,
+        grid: &Grid,
+        tiles: &[Tile<P>],
+        grid_index: usize,
+        _: &mut dyn RngCore,
+    ) -> Option<usize> {
+        options(grid, grid_index)
+            .into_iter()
+            .rev()
+            .max_by_key(|&tile_index| tiles[tile_index].frequency)
+    }
+}
+
+fn options(grid: &Grid, grid_index: usize) -> Vec<usize> {
+    grid.get_index(grid_index)
+        .map(|cell| cell.options().iter().collect())
+        .unwrap_or_default()
+}
+
+/// Falls back to sampling uniformly if the weights are all zero or not finite
+fn sample_weighted(
+    options: &[usize],
+    rng: &mut dyn RngCore,
+    weight: impl Fn(usize) -> f64,
+) -> Option<usize> {
+    match WeightedIndex::new(options.iter().map(|&tile_index| weight(tile_index))) {
+        Ok(dist) => options.get(dist.sample(rng)).copied(),
+        Err(_) => options.choose(rng).copied(),
+    }
+}
@@ -0,0 +1,75 @@
+//! Checks that backtracking recovers from contradictions and leaves a consistent grid.
+
+use image::RgbaImage;
+use wfc_overlapping_model::{
+    grid::Propagation, heuristic::Heuristic, image_impls::Symmetry, tile::Direction,
+    value_selector::Uniform, Generator, GeneratorBuilder,
+};
+
+const SIZE: usize = 12;
+const SEEDS: u64 = 10;
+
+/// Collapses in reading order with uniform choices, which runs into contradictions on the waves sample
+fn generator(sample: &RgbaImage, propagation: Propagation, seed: u64) -> Generator {
+    GeneratorBuilder::new(sample.clone())
+        .symmetry(Symmetry::Dihedral)
+        .output_size(SIZE, SIZE)
+        .propagation(propagation)
+        .heuristic(Heuristic::Scanline.build())
+        .value_selector(Uniform)
+        .backtracking(10_000)
+        .max_attempts(1)
+        .seed(seed)
+        .build()
+        .unwrap()
+}
+
+/// Whether every pair of neighboring cells holds tiles that may neighbor each other
+fn is_consistent(generator: &Generator) -> bool {
+    let grid = generator.grid();
+    let tiles = generator.tiles();
+    let tile_at = |grid_index: usize| grid.get_index(grid_index).unwrap().options().iter().next();
+    (0..grid.width() * grid.height()).all(|grid_index| {
+        let tile_index = tile_at(grid_index).unwrap();
+        [Direction::Down, Direction::Right]
+            .into_iter()
+            .all(|direction| {
+                grid.index_in_direction(grid_index, direction)
+                    .is_none_or(|neighbor_index| {
+                        let neighbor_tile = tile_at(neighbor_index).unwrap();
+                        tiles[tile_index].neighbors[direction].contains(neighbor_tile)
+                    })
+            })
+    })
+}
+
+#[test]
+fn backtracking_recovers_from_contradictions() {
+    let sample = image::open("tile_examples/waves.png")
+        .expect("Example tiles should exist")
+        .into_rgba8();
+    let mut total_backtracks = 0;
+    for seed in 0..SEEDS {
+        let mut outputs = Vec::new();
+        for propagation in [Propagation::Full, Propagation::SupportCount] {
+            let mut generator = generator(&sample, propagation, seed);
+            let image = generator
+                .generate()
+                .expect("Backtracking should recover without restarting");
+            assert_eq!(generator.grid().attempts(), 0);
+            assert!(
+                is_consistent(&generator),
+                "seed {seed} left incompatible neighbors"
+            );
+            let backtracks = generator.grid().backtracking().unwrap().backtracks;
+            outputs.push((image, backtracks));
+        }
+        // both propagations reach the same options after every decision, so they must make the same choices
+        assert!(
+            outputs[0] == outputs[1],
+            "seed {seed} differs between propagations"
+        );
+        total_backtracks += outputs[0].1;
+    }
+    assert!(total_backtracks > 0, "no seed ran into a contradiction");
+}
@@ -0,0 +1,77 @@
+//! Checks that constraints which cannot be met, or do not fit the output, are rejected instead of retried forever.
+
+use image::{GrayAlphaImage, LumaA, Rgba, RgbaImage};
+use wfc_overlapping_model::{Error, GeneratorBuilder};
+
+const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+fn city() -> RgbaImage {
+    image::open("tile_examples/city.png")
+        .expect("Example tiles should exist")
+        .into_rgba8()
+}
+
+#[test]
+fn conflicting_fixed_cells_are_invalid() {
+    let checkerboard = RgbaImage::from_fn(6, 6, |x, y| if (x + y) % 2 == 0 { WHITE } else { RED });
+    let single = RgbaImage::from_fn(
+        6,
+        6,
+        |x, y| if (x, y) == (0, 0) { RED } else { Rgba([0; 4]) },
+    );
+    let build = |constraint_image| {
+        GeneratorBuilder::new(city())
+            .output_size(6, 6)
+            .constraint_image(constraint_image)
+            .build()
+    };
+    assert!(build(single).is_ok());
+    assert!(matches!(
+        build(checkerboard),
+        Err(Error::InvalidConstraint(_))
+    ));
+}
+
+#[test]
+fn conflicting_edges_are_invalid() {
+    let build = |edges: &[&str]| {
+        edges
+            .iter()
+            .fold(
+                GeneratorBuilder::new(city()).output_size(5, 1),
+                |builder, edge| builder.edge_constraint(edge.parse().unwrap()),
+            )
+            .build()
+    };
+    assert!(build(&["top=#ffffff"]).is_ok());
+    assert!(build(&["bottom=#ff0000"]).is_ok());
+    // a single row is both the top and the bottom edge, so no cell can be white and red at once
+    assert!(matches!(
+        build(&["top=#ffffff", "bottom=#ff0000"]),
+        Err(Error::InvalidConstraint(_))
+    ));
+}
+
+#[test]
+fn images_of_another_size_are_invalid() {
+    let build = |width, height| {
+        GeneratorBuilder::new(city())
+            .output_size(6, 6)
+            .constraint_image(RgbaImage::new(width, height))
+            .mask_image(GrayAlphaImage::from_pixel(6, 6, LumaA([255, 255])))
+            .build()
+    };
+    assert!(build(6, 6).is_ok());
+    for (width, height) in [(5, 6), (6, 7)] {
+        assert!(matches!(
+            build(width, height),
+            Err(Error::InvalidConstraint(_))
+        ));
+    }
+    let mask = GeneratorBuilder::new(city())
+        .output_size(6, 6)
+        .mask_image(GrayAlphaImage::new(6, 5))
+        .build();
+    assert!(matches!(mask, Err(Error::InvalidConstraint(_))));
+}
@@ -0,0 +1,51 @@
+//! Checks that the same seed generates the same output, bit for bit.
+
+use clap::ValueEnum;
+use image::RgbaImage;
+use wfc_overlapping_model::{
+    grid::Propagation, heuristic::Heuristic, image_impls::Symmetry, GeneratorBuilder,
+};
+
+const SEED: u64 = 7;
+
+fn generate(
+    sample: &RgbaImage,
+    heuristic: Heuristic,
+    propagation: Propagation,
+    backtracking: bool,
+) -> RgbaImage {
+    let mut builder = GeneratorBuilder::new(sample.clone())
+        .symmetry(Symmetry::Dihedral)
+        .output_size(12, 12)
+        .heuristic(heuristic.build())
+        .propagation(propagation)
+        .seed(SEED);
+    if backtracking {
+        builder = builder.backtracking(1_000);
+    }
+    builder
+        .build()
+        .and_then(|mut generator| generator.generate())
+        .expect("Example tiles should generate")
+}
+
+#[test]
+fn same_seed_same_output() {
+    let sample = image::open("tile_examples/Flowers.png")
+        .expect("Example tiles should exist")
+        .into_rgba8();
+    for &heuristic in Heuristic::value_variants() {
+        for propagation in [Propagation::Full, Propagation::SupportCount] {
+            for backtracking in [false, true] {
+                let first = generate(&sample, heuristic, propagation, backtracking);
+                let second = generate(&sample, heuristic, propagation, backtracking);
+                assert!(
+                    first == second,
+                    "{} with {} propagation differs between runs, backtracking: {backtracking}",
+                    heuristic.to_possible_value().unwrap().get_name(),
+                    propagation.to_possible_value().unwrap().get_name()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Checks that hashed tile extraction produces the same ruleset as comparing every pair of tiles, and that
+//! tiles which cannot be extracted are rejected.
+
+use std::fs;
+
+use wfc_overlapping_model::{
+    domain::Domain,
+    image_impls::{BorderStyle, Symmetry},
+    ruleset::Ruleset,
+    tile::{self, Directions, Tile},
+    Error,
+};
+
+/// The edges and neighbors of a kept tile
+type Rules = (Directions<bool>, Directions<Domain>);
+
+/// The frequency of every tile, and the edges and neighbors of every tile that was kept,
+/// limited to kept tiles since merged duplicates never appear in the grid
+fn ruleset(tiles: &[Tile]) -> Vec<(u32, Option<Rules>)> {
+    let kept = Domain::from_indexes(
+        tiles.len(),
+        (0..tiles.len()).filter(|&tile_index| tiles[tile_index].frequency != 0),
+    );
+    tiles
+        .iter()
+        .map(|tile| {
+            let rules = (tile.frequency != 0).then(|| {
+                let mut neighbors = tile.neighbors.clone();
+                for domain in [
+                    &mut neighbors.up,
+                    &mut neighbors.down,
+                    &mut neighbors.left,
+                    &mut neighbors.right,
+                ] {
+                    domain.intersect_with(&kept);
+                }
+                (tile.edges.clone(), neighbors)
+            });
+            (tile.frequency, rules)
+        })
+        .collect()
+}
+
+#[test]
+fn hashed_extraction_matches_naive_extraction() {
+    let mut paths: Vec<_> = fs::read_dir("tile_examples")
+        .expect("Example tiles should exist")
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        let sample = image::open(&path).unwrap().into_rgba8();
+        for border_style in [BorderStyle::Looping, BorderStyle::Clamped] {
+            for symmetry in [Symmetry::None, Symmetry::Dihedral] {
+                let hashed = tile::extract_tiles(&sample, 3, border_style, symmetry, false);
+                let naive = tile::extract_tiles_naive(&sample, 3, border_style, symmetry, false);
+                assert!(
+                    ruleset(&hashed) == ruleset(&naive),
+                    "rulesets differ for {}",
+                    path.display()
+                );
+                let kept = hashed.iter().filter(|tile| tile.frequency != 0).count();
+                assert_eq!(
+                    tile::count_patterns(&sample, 3, border_style, symmetry),
+                    kept,
+                    "pattern count differs for {}",
+                    path.display()
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn tiles_must_fit_the_sample() {
+    let sample = image::open("tile_examples/city.png")
+        .expect("Example tiles should exist")
+        .into_rgba8();
+    let extract = |tile_size| {
+        Ruleset::from_sample(
+            &sample,
+            tile_size,
+            BorderStyle::Looping,
+            Symmetry::None,
+            false,
+        )
+    };
+    assert!(extract(sample.width()).is_ok());
+    for tile_size in [0, 2, sample.width() + 2, 101] {
+        assert!(matches!(
+            extract(tile_size),
+            Err(Error::InvalidTileSize(size)) if size == tile_size
+        ));
+    }
+}
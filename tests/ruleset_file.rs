@@ -0,0 +1,116 @@
+//! Checks that rulesets survive a save and load, and that files which do not match are rejected.
+
+use std::io::ErrorKind;
+
+use image::Rgba;
+use wfc_overlapping_model::{
+    domain::Domain,
+    image_impls::{BorderStyle, Symmetry},
+    ruleset::Ruleset,
+    tile::{Directions, Tile, TileImage},
+    Error, GeneratorBuilder,
+};
+
+fn city() -> Ruleset {
+    let sample = image::open("tile_examples/city.png")
+        .expect("Example tiles should exist")
+        .into_rgba8();
+    Ruleset::from_sample(&sample, 3, BorderStyle::Clamped, Symmetry::Dihedral, false).unwrap()
+}
+
+/// Single pixel tiles that may each neighbor the listed tiles in every direction
+fn hand_made(neighbors: &[&[usize]]) -> Ruleset {
+    let tile_count = neighbors.len();
+    let tiles = neighbors
+        .iter()
+        .map(|neighbors| Tile {
+            image: TileImage::new(1, 1),
+            neighbors: Directions::splat(Domain::from_indexes(
+                tile_count,
+                neighbors.iter().copied(),
+            )),
+            frequency: 1,
+            border_style: BorderStyle::Clamped,
+            edges: Directions::default(),
+        })
+        .collect();
+    Ruleset::from_tiles(tiles, 1, BorderStyle::Clamped)
+}
+
+fn saved(ruleset: &Ruleset) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    ruleset.save(&mut bytes).unwrap();
+    bytes
+}
+
+fn load_error(bytes: &[u8]) -> ErrorKind {
+    Ruleset::<Rgba<u8>>::load(bytes)
+        .err()
+        .expect("Ruleset should be rejected")
+        .kind()
+}
+
+#[test]
+fn round_trip() {
+    let ruleset = city();
+    let loaded = Ruleset::<Rgba<u8>>::load(saved(&ruleset).as_slice()).unwrap();
+    assert_eq!(loaded.tile_size(), ruleset.tile_size());
+    assert_eq!(loaded.border_style() as u8, ruleset.border_style() as u8);
+    assert_eq!(loaded.tiles().len(), ruleset.tiles().len());
+    for (loaded, tile) in loaded.tiles().iter().zip(ruleset.tiles()) {
+        assert!(loaded.image == tile.image);
+        assert_eq!(loaded.frequency, tile.frequency);
+        assert_eq!(loaded.edges, tile.edges);
+        assert_eq!(loaded.neighbors, tile.neighbors);
+    }
+}
+
+#[test]
+fn mismatched_files_are_rejected() {
+    let bytes = saved(&city());
+
+    let mut wrong_magic = bytes.clone();
+    wrong_magic[0] = b'X';
+    assert_eq!(load_error(&wrong_magic), ErrorKind::InvalidData);
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(load_error(&wrong_version), ErrorKind::InvalidData);
+
+    let wrong_pixel = Ruleset::<Rgba<u16>>::load(bytes.as_slice()).err();
+    assert_eq!(
+        wrong_pixel.map(|error| error.kind()),
+        Some(ErrorKind::InvalidData)
+    );
+
+    // magic, version, channel count and max value come before the tile size, which is followed by the border
+    // style and the tile count
+    let tile_size = 4 + 4 + 1 + 8;
+    let mut zero_size = bytes.clone();
+    zero_size[tile_size..tile_size + 4].copy_from_slice(&0u32.to_le_bytes());
+    assert_eq!(load_error(&zero_size), ErrorKind::InvalidData);
+
+    let tile_count = tile_size + 4 + 1;
+    let mut huge_count = bytes[..tile_count + 4].to_vec();
+    huge_count[tile_count..].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(load_error(&huge_count), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn one_sided_neighbors_are_rejected() {
+    let symmetric = hand_made(&[&[0, 1, 2], &[0, 1, 2], &[0, 1, 2]]);
+    assert!(Ruleset::<Rgba<u8>>::load(saved(&symmetric).as_slice()).is_ok());
+    // tiles 0 and 2 may neighbor tile 1, but tile 1 only lists itself
+    let one_sided = hand_made(&[&[0, 1, 2], &[1], &[0, 1, 2]]);
+    assert_eq!(load_error(&saved(&one_sided)), ErrorKind::InvalidData);
+}
+
+#[test]
+fn empty_rulesets_are_rejected() {
+    let empty = hand_made(&[]);
+    assert_eq!(load_error(&saved(&empty)), ErrorKind::InvalidData);
+    assert!(matches!(
+        GeneratorBuilder::from_ruleset(empty).build(),
+        Err(Error::NoTiles)
+    ));
+}
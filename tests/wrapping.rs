@@ -0,0 +1,56 @@
+//! Checks that wrapped outputs tile seamlessly across their opposite edges.
+
+use wfc_overlapping_model::{
+    grid::{Propagation, Wrapping},
+    image_impls::{BorderStyle, Symmetry},
+    tile::Direction,
+    GeneratorBuilder,
+};
+
+const WIDTH: usize = 12;
+const HEIGHT: usize = 10;
+
+#[test]
+fn wrapped_output_is_seamless() {
+    let sample = image::open("tile_examples/Flowers.png")
+        .expect("Example tiles should exist")
+        .into_rgba8();
+    for propagation in [Propagation::Full, Propagation::SupportCount] {
+        let mut generator = GeneratorBuilder::new(sample.clone())
+            .symmetry(Symmetry::Dihedral)
+            .border_style(BorderStyle::Looping)
+            .output_size(WIDTH, HEIGHT)
+            .wrapping(Wrapping::Both)
+            .propagation(propagation)
+            .seed(3)
+            .build()
+            .unwrap();
+        generator.generate().expect("Example tiles should generate");
+        assert!(generator.is_seamless());
+
+        let tiles = generator.tiles();
+        let tile_at = |x: usize, y: usize| {
+            let options = generator.grid().get_index(x + y * WIDTH).unwrap().options();
+            assert_eq!(options.len(), 1);
+            options.iter().next().unwrap()
+        };
+        let allowed = |tile_index: usize, direction: Direction, neighbor_tile: usize| {
+            tiles[tile_index].neighbors[direction].contains(neighbor_tile)
+                && tiles[neighbor_tile].neighbors[direction.opposing()].contains(tile_index)
+        };
+        for y in 0..HEIGHT {
+            assert!(allowed(
+                tile_at(WIDTH - 1, y),
+                Direction::Right,
+                tile_at(0, y)
+            ));
+        }
+        for x in 0..WIDTH {
+            assert!(allowed(
+                tile_at(x, HEIGHT - 1),
+                Direction::Down,
+                tile_at(x, 0)
+            ));
+        }
+    }
+}